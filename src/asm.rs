@@ -0,0 +1,951 @@
+// CHIP-8 assembler: a builder for putting programs together in code, and a parser for
+// a small line-based text syntax built on top of it
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::cpu::{decode, encode, Instruction, PROGRAM_START};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    InvalidOperands { line: usize, operands: String },
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    AddressOutOfRange(usize),
+    MisplacedOrigin { line: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic {}", line, mnemonic)
+            }
+            AsmError::InvalidOperands { line, operands } => {
+                write!(f, "line {}: invalid operands '{}'", line, operands)
+            }
+            AsmError::UndefinedLabel(label) => write!(f, "undefined label {}", label),
+            AsmError::DuplicateLabel(label) => write!(f, "label {} defined twice", label),
+            AsmError::AddressOutOfRange(addr) => {
+                write!(f, "address {:#X} doesn't fit in 12 bits", addr)
+            }
+            AsmError::MisplacedOrigin { line } => {
+                write!(
+                    f,
+                    "line {}: ORG has to come before any code or labels",
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// why a hex opcode dump couldn't be parsed, with the offending word and its position
+// (counting from 0) in the dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    BadLength { word: usize, text: String },
+    NotHex { word: usize, text: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::BadLength { word, text } => {
+                write!(f, "opcode {} '{}' isn't 4 hex digits long", word, text)
+            }
+            ParseError::NotHex { word, text } => {
+                write!(f, "opcode {} '{}' isn't hexadecimal", word, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+enum Item {
+    Instruction(Instruction),
+    // an instruction taking an address, to be filled in with the label's once every
+    // label is known
+    LabelRef(fn(u16) -> Instruction, String),
+    Bytes(Vec<u8>),
+}
+
+// Builds a program an instruction at a time. Labels name the address of whatever comes
+// next and can be referred to before they are defined; they're resolved by finish.
+pub struct Assembler {
+    origin: usize,
+    items: Vec<Item>,
+    labels: HashMap<String, usize>,
+    len: usize,
+    duplicate_label: Option<String>,
+}
+
+impl Assembler {
+    // assemble a program to be loaded at PROGRAM_START
+    pub fn new() -> Assembler {
+        Assembler::with_origin(PROGRAM_START)
+    }
+
+    pub fn with_origin(origin: usize) -> Assembler {
+        Assembler {
+            origin,
+            items: Vec::new(),
+            labels: HashMap::new(),
+            len: 0,
+            duplicate_label: None,
+        }
+    }
+
+    // the address the next instruction will be assembled at
+    pub fn address(&self) -> usize {
+        self.origin + self.len
+    }
+
+    pub fn instruction(&mut self, instruction: Instruction) -> &mut Self {
+        self.items.push(Item::Instruction(instruction));
+        self.len += 2;
+        self
+    }
+
+    // an instruction whose address operand is a label, e.g.
+    // `asm.label_ref(Instruction::Jump, "loop")`
+    pub fn label_ref(&mut self, op: fn(u16) -> Instruction, label: &str) -> &mut Self {
+        self.items.push(Item::LabelRef(op, label.to_string()));
+        self.len += 2;
+        self
+    }
+
+    // raw data, such as sprites, placed between instructions
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.items.push(Item::Bytes(bytes.to_vec()));
+        self.len += bytes.len();
+        self
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        let address = self.address();
+        if self.labels.insert(name.to_string(), address).is_some() {
+            self.duplicate_label.get_or_insert_with(|| name.to_string());
+        }
+        self
+    }
+
+    pub fn finish(&self) -> Result<Vec<u8>, AsmError> {
+        if let Some(label) = &self.duplicate_label {
+            return Err(AsmError::DuplicateLabel(label.clone()));
+        }
+
+        let mut out = Vec::with_capacity(self.len);
+        for item in &self.items {
+            let instruction = match item {
+                Item::Instruction(instruction) => *instruction,
+                Item::LabelRef(op, label) => {
+                    let address = *self
+                        .labels
+                        .get(label)
+                        .ok_or_else(|| AsmError::UndefinedLabel(label.clone()))?;
+                    op(address_operand(address)?)
+                }
+                Item::Bytes(bytes) => {
+                    out.extend_from_slice(bytes);
+                    continue;
+                }
+            };
+            out.extend_from_slice(&encode(instruction).to_be_bytes());
+        }
+        Ok(out)
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn address_operand(address: usize) -> Result<u16, AsmError> {
+    if address > 0xFFF {
+        return Err(AsmError::AddressOutOfRange(address));
+    }
+    Ok(address as u16)
+}
+
+// One operand of a line of assembly. Register and keyword names are case-insensitive,
+// labels aren't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI, // [I]
+    Delay,     // DT
+    Sound,     // ST
+    Key,       // K
+    Font,      // F
+    LargeFont, // HF
+    Bcd,       // B
+    Flags,     // R
+    Number(usize),
+    Label(String),
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Delay,
+        "ST" => Operand::Sound,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::LargeFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            Operand::V(u8::from_str_radix(&upper[1..], 16).ok()?)
+        }
+        _ => {
+            let number = if let Some(hex) = upper.strip_prefix("0X") {
+                usize::from_str_radix(hex, 16)
+            } else if let Some(binary) = upper.strip_prefix("0B") {
+                usize::from_str_radix(binary, 2)
+            } else {
+                upper.parse()
+            };
+            match number {
+                Ok(n) => Operand::Number(n),
+                Err(_) if is_label(text) => Operand::Label(text.to_string()),
+                Err(_) => return None,
+            }
+        }
+    };
+    Some(operand)
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Assemble a program for PROGRAM_START from text, one instruction per line, using the
+// usual Cowgod mnemonics:
+//
+//     start:  LD V0, 5      ; comments run from ';' to the end of the line
+//             ADD V0, V1
+//             JP start
+//
+// Addresses can be numbers or labels, and `DB 1, 2, 3` emits raw bytes. `ORG 0x600`
+// before anything else assembles the program for loading somewhere other than
+// PROGRAM_START instead.
+pub fn assemble_text(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let line_number = i + 1;
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, rest)) = line.split_once(':') {
+            if !is_label(label.trim()) {
+                break;
+            }
+            asm.label(label.trim());
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let invalid = || AsmError::InvalidOperands {
+            line: line_number,
+            operands: operands.trim().to_string(),
+        };
+        let parsed: Option<Vec<Operand>> = operands
+            .split(',')
+            .map(str::trim)
+            .filter(|operand| !operand.is_empty())
+            .map(parse_operand)
+            .collect();
+        let parsed = parsed.ok_or_else(invalid)?;
+
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        if mnemonic == "DB" {
+            let bytes: Option<Vec<u8>> = parsed
+                .iter()
+                .map(|operand| match operand {
+                    Operand::Number(n) => u8::try_from(*n).ok(),
+                    _ => None,
+                })
+                .collect();
+            asm.bytes(
+                &bytes
+                    .filter(|bytes| !bytes.is_empty())
+                    .ok_or_else(invalid)?,
+            );
+            continue;
+        }
+        if mnemonic == "ORG" {
+            if !asm.items.is_empty() || !asm.labels.is_empty() {
+                return Err(AsmError::MisplacedOrigin { line: line_number });
+            }
+            asm = match parsed[..] {
+                [Operand::Number(origin)] if origin <= 0xFFF => Assembler::with_origin(origin),
+                _ => return Err(invalid()),
+            };
+            continue;
+        }
+
+        match parse_line(&mnemonic, &parsed) {
+            Parsed::Instruction(instruction) => asm.instruction(instruction),
+            Parsed::LabelRef(op, label) => asm.label_ref(op, &label),
+            Parsed::InvalidOperands => return Err(invalid()),
+            Parsed::UnknownMnemonic => {
+                return Err(AsmError::UnknownMnemonic {
+                    line: line_number,
+                    mnemonic,
+                })
+            }
+        };
+    }
+
+    asm.finish()
+}
+
+enum Parsed {
+    Instruction(Instruction),
+    LabelRef(fn(u16) -> Instruction, String),
+    InvalidOperands,
+    UnknownMnemonic,
+}
+
+const MNEMONICS: [&str; 28] = [
+    "HALT", "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE",
+    "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP",
+    "SKNP", "PLANE",
+];
+
+fn parse_line(mnemonic: &str, operands: &[Operand]) -> Parsed {
+    if !MNEMONICS.contains(&mnemonic) {
+        return Parsed::UnknownMnemonic;
+    }
+
+    use Instruction as In;
+    use Operand::*;
+
+    let nn = |n: usize| u8::try_from(n).ok();
+    let n4 = |n: usize| u8::try_from(n).ok().filter(|&n| n <= 0xF);
+    // an address operand, which may be a label to be resolved later
+    let target = |op: fn(u16) -> Instruction, operand: &Operand| match operand {
+        Number(addr) if *addr <= 0xFFF => Parsed::Instruction(op(*addr as u16)),
+        Label(label) => Parsed::LabelRef(op, label.clone()),
+        _ => Parsed::InvalidOperands,
+    };
+
+    let instruction = match (mnemonic, operands) {
+        ("HALT", []) => Some(In::Halt),
+        ("CLS", []) => Some(In::ClearScreen),
+        ("RET", []) => Some(In::Return),
+        ("SCD", [Number(n)]) => n4(*n).map(In::ScrollDown),
+        ("SCR", []) => Some(In::ScrollRight),
+        ("SCL", []) => Some(In::ScrollLeft),
+        ("EXIT", []) => Some(In::Exit),
+        ("LOW", []) => Some(In::LowRes),
+        ("HIGH", []) => Some(In::HighRes),
+        ("SYS", [addr]) => return target(In::SysCall, addr),
+        ("JP", [V(0), addr]) => return target(In::JumpV0, addr),
+        ("JP", [addr]) => return target(In::Jump, addr),
+        ("CALL", [addr]) => return target(In::Call, addr),
+        ("SE", [V(x), V(y)]) => Some(In::SkipEqXY { x: *x, y: *y }),
+        ("SE", [V(x), Number(n)]) => nn(*n).map(|nn| In::SkipEqImm { x: *x, nn }),
+        ("SNE", [V(x), V(y)]) => Some(In::SkipNeXY { x: *x, y: *y }),
+        ("SNE", [V(x), Number(n)]) => nn(*n).map(|nn| In::SkipNeImm { x: *x, nn }),
+        ("LD", [V(x), V(y)]) => Some(In::LoadXY { x: *x, y: *y }),
+        ("LD", [V(x), Number(n)]) => nn(*n).map(|nn| In::LoadImm { x: *x, nn }),
+        ("LD", [I, addr]) => return target(In::LoadIndex, addr),
+        ("LD", [V(x), Delay]) => Some(In::LoadDelay { x: *x }),
+        ("LD", [V(x), Key]) => Some(In::WaitKey { x: *x }),
+        ("LD", [Delay, V(x)]) => Some(In::SetDelay { x: *x }),
+        ("LD", [Sound, V(x)]) => Some(In::SetSound { x: *x }),
+        ("LD", [Font, V(x)]) => Some(In::LoadFont { x: *x }),
+        ("LD", [LargeFont, V(x)]) => Some(In::LoadLargeFont { x: *x }),
+        ("LD", [Bcd, V(x)]) => Some(In::StoreBcd { x: *x }),
+        ("LD", [IndirectI, V(x)]) => Some(In::StoreRegs { x: *x }),
+        ("LD", [V(x), IndirectI]) => Some(In::LoadRegs { x: *x }),
+        ("LD", [Flags, V(x)]) => Some(In::SaveFlags { x: *x }),
+        ("LD", [V(x), Flags]) => Some(In::LoadFlags { x: *x }),
+        ("ADD", [V(x), V(y)]) => Some(In::AddXY { x: *x, y: *y }),
+        ("ADD", [V(x), Number(n)]) => nn(*n).map(|nn| In::AddImm { x: *x, nn }),
+        ("ADD", [I, V(x)]) => Some(In::AddIndex { x: *x }),
+        ("OR", [V(x), V(y)]) => Some(In::OrXY { x: *x, y: *y }),
+        ("AND", [V(x), V(y)]) => Some(In::AndXY { x: *x, y: *y }),
+        ("XOR", [V(x), V(y)]) => Some(In::XorXY { x: *x, y: *y }),
+        ("SUB", [V(x), V(y)]) => Some(In::SubXY { x: *x, y: *y }),
+        ("SUBN", [V(x), V(y)]) => Some(In::SubnXY { x: *x, y: *y }),
+        // with Vy left out, shift Vx in place whichever register the CPU shifts
+        ("SHR", [V(x)]) => Some(In::ShrXY { x: *x, y: *x }),
+        ("SHR", [V(x), V(y)]) => Some(In::ShrXY { x: *x, y: *y }),
+        ("SHL", [V(x)]) => Some(In::ShlXY { x: *x, y: *x }),
+        ("SHL", [V(x), V(y)]) => Some(In::ShlXY { x: *x, y: *y }),
+        ("RND", [V(x), Number(n)]) => nn(*n).map(|nn| In::Random { x: *x, nn }),
+        ("DRW", [V(x), V(y), Number(n)]) => n4(*n).map(|n| In::Draw { x: *x, y: *y, n }),
+        ("SKP", [V(x)]) => Some(In::SkipKeyPressed { x: *x }),
+        ("SKNP", [V(x)]) => Some(In::SkipKeyNotPressed { x: *x }),
+        ("PLANE", [Number(n)]) => n4(*n).map(In::SelectPlanes),
+        _ => None,
+    };
+
+    instruction.map_or(Parsed::InvalidOperands, Parsed::Instruction)
+}
+
+// The text assemble_text accepts for a single instruction. Opcodes that don't decode to
+// anything come out as a DB of their two bytes, so they still reassemble to themselves.
+pub fn disassemble_instruction(instruction: Instruction) -> String {
+    use Instruction as In;
+
+    match instruction {
+        In::Halt => "HALT".to_string(),
+        In::ClearScreen => "CLS".to_string(),
+        In::Return => "RET".to_string(),
+        In::ScrollDown(n) => format!("SCD {}", n),
+        In::ScrollRight => "SCR".to_string(),
+        In::ScrollLeft => "SCL".to_string(),
+        In::Exit => "EXIT".to_string(),
+        In::LowRes => "LOW".to_string(),
+        In::HighRes => "HIGH".to_string(),
+        In::SysCall(nnn) => format!("SYS {:#05X}", nnn),
+        In::Jump(nnn) => format!("JP {:#05X}", nnn),
+        In::Call(nnn) => format!("CALL {:#05X}", nnn),
+        In::SkipEqImm { x, nn } => format!("SE V{:X}, {:#04X}", x, nn),
+        In::SkipNeImm { x, nn } => format!("SNE V{:X}, {:#04X}", x, nn),
+        In::SkipEqXY { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        In::LoadImm { x, nn } => format!("LD V{:X}, {:#04X}", x, nn),
+        In::AddImm { x, nn } => format!("ADD V{:X}, {:#04X}", x, nn),
+        In::LoadXY { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        In::OrXY { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        In::AndXY { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        In::XorXY { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        In::AddXY { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        In::SubXY { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        In::ShrXY { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        In::SubnXY { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        In::ShlXY { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        In::SkipNeXY { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        In::LoadIndex(nnn) => format!("LD I, {:#05X}", nnn),
+        In::JumpV0(nnn) => format!("JP V0, {:#05X}", nnn),
+        In::Random { x, nn } => format!("RND V{:X}, {:#04X}", x, nn),
+        In::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        In::SkipKeyPressed { x } => format!("SKP V{:X}", x),
+        In::SkipKeyNotPressed { x } => format!("SKNP V{:X}", x),
+        In::LoadDelay { x } => format!("LD V{:X}, DT", x),
+        In::WaitKey { x } => format!("LD V{:X}, K", x),
+        In::SetDelay { x } => format!("LD DT, V{:X}", x),
+        In::SetSound { x } => format!("LD ST, V{:X}", x),
+        In::AddIndex { x } => format!("ADD I, V{:X}", x),
+        In::LoadFont { x } => format!("LD F, V{:X}", x),
+        In::SelectPlanes(n) => format!("PLANE {}", n),
+        In::LoadLargeFont { x } => format!("LD HF, V{:X}", x),
+        In::StoreBcd { x } => format!("LD B, V{:X}", x),
+        In::StoreRegs { x } => format!("LD [I], V{:X}", x),
+        In::LoadRegs { x } => format!("LD V{:X}, [I]", x),
+        In::SaveFlags { x } => format!("LD R, V{:X}", x),
+        In::LoadFlags { x } => format!("LD V{:X}, R", x),
+        In::Unknown(opcode) => format!("DB {:#04X}, {:#04X}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+// Decode `rom` two bytes at a time, yielding each instruction with its offset in the
+// ROM. An odd byte left at the end can't be a whole opcode, so it comes out as an
+// Unknown holding that byte in the high half.
+pub fn instructions(rom: &[u8]) -> impl Iterator<Item = (usize, Instruction)> + '_ {
+    rom.chunks(2).enumerate().map(|(i, chunk)| {
+        let instruction = match *chunk {
+            [hi, lo] => decode(u16::from_be_bytes([hi, lo])),
+            [byte] => Instruction::Unknown((byte as u16) << 8),
+            _ => unreachable!(),
+        };
+        (i * 2, instruction)
+    })
+}
+
+// Disassemble a whole ROM, one instruction per line. Every two bytes are treated as an
+// instruction, so any data comes out as whatever it happens to decode to; a trailing
+// odd byte comes out as a DB.
+pub fn disassemble(rom: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in rom.chunks(2) {
+        let line = match *chunk {
+            [hi, lo] => disassemble_instruction(decode(u16::from_be_bytes([hi, lo]))),
+            [byte] => format!("DB {:#04X}", byte),
+            _ => unreachable!(),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+// A heuristic for spotting self-modifying ROMs loaded at PROGRAM_START: whether any
+// FX55 stores V0..=VX over the program code before it. I is only tracked through the
+// ANNN instructions preceding each store, in ROM order rather than execution order,
+// and anything else that changes I makes it unknown until the next ANNN.
+pub fn detects_self_modification(rom: &[u8]) -> bool {
+    use Instruction as In;
+
+    let mut index = None;
+    for (offset, instruction) in instructions(rom) {
+        match instruction {
+            In::LoadIndex(nnn) => index = Some(nnn as usize),
+            In::AddIndex { .. } | In::LoadFont { .. } | In::LoadLargeFont { .. } => index = None,
+            In::StoreRegs { x } => {
+                let pc = PROGRAM_START + offset;
+                if index.is_some_and(|i| i < pc && i + x as usize >= PROGRAM_START) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+pub fn self_modification_example() {
+    // 7001: V0 += 1, A200: I = 0x200, F055: store V0 over the first instruction,
+    // 1200: run it again
+    assert!(detects_self_modification(&[
+        0x70, 0x01, 0xA2, 0x00, 0xF0, 0x55, 0x12, 0x00
+    ]));
+
+    // the same, storing into the spare byte after the program instead
+    assert!(!detects_self_modification(&[
+        0xA2, 0x06, 0xF0, 0x55, 0x12, 0x00, 0x00
+    ]));
+
+    // I pointed at the code, but moved on by FX1E before the store
+    assert!(!detects_self_modification(&[
+        0xA2, 0x00, 0xF1, 0x1E, 0xF0, 0x55
+    ]));
+}
+
+// Parse a ROM written out as whitespace-separated opcodes, e.g. "2100 8014 0000", into
+// the bytes to load, each opcode big-endian as in memory
+pub fn parse_hex_opcodes(s: &str) -> Result<Vec<u8>, ParseError> {
+    let mut rom = Vec::new();
+    for (word, text) in s.split_whitespace().enumerate() {
+        if text.len() != 4 {
+            return Err(ParseError::BadLength {
+                word,
+                text: text.to_string(),
+            });
+        }
+        // from_str_radix alone would also accept a leading '+'
+        let opcode = u16::from_str_radix(text, 16)
+            .ok()
+            .filter(|_| text.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| ParseError::NotHex {
+                word,
+                text: text.to_string(),
+            })?;
+        rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+    Ok(rom)
+}
+
+pub fn parse_hex_example() {
+    assert_eq!(
+        parse_hex_opcodes("2100 2100 0000\n8014 8014 00EE"),
+        Ok(vec![
+            0x21, 0x00, 0x21, 0x00, 0x00, 0x00, 0x80, 0x14, 0x80, 0x14, 0x00, 0xEE
+        ])
+    );
+    assert_eq!(parse_hex_opcodes("  "), Ok(vec![]));
+
+    assert_eq!(
+        parse_hex_opcodes("2100 801"),
+        Err(ParseError::BadLength {
+            word: 1,
+            text: "801".to_string()
+        })
+    );
+    for bad in ["21G0", "+100", "0x21"] {
+        assert_eq!(
+            parse_hex_opcodes(bad),
+            Err(ParseError::NotHex {
+                word: 0,
+                text: bad.to_string()
+            })
+        );
+    }
+    println!("{}", parse_hex_opcodes("2100 80141").unwrap_err());
+}
+
+// A disassembly of a ROM loaded at `load_addr`, with every JP and CALL target inside
+// the ROM given a label (L_ and its address in hex) that the jumps refer to, and each
+// line commented with its address and opcode:
+//
+//         CALL L_0204         ; 200: 2204
+//         HALT                ; 202: 0000
+//     L_0204:
+//         RET                 ; 204: 00EE
+//
+// Like disassemble, the output can be fed back to assemble_text. A ROM loaded anywhere
+// other than PROGRAM_START gets an ORG line first, so the labels reassemble to the
+// same addresses.
+pub fn listing(rom: &[u8], load_addr: usize) -> String {
+    let in_rom = |addr: usize| {
+        addr >= load_addr && addr < load_addr + rom.len() && (addr - load_addr).is_multiple_of(2)
+    };
+    let target = |instruction| match instruction {
+        Instruction::Jump(nnn) | Instruction::Call(nnn) => Some(nnn as usize),
+        _ => None,
+    };
+    let labels: HashSet<usize> = instructions(rom)
+        .filter_map(|(_, instruction)| target(instruction))
+        .filter(|&addr| in_rom(addr))
+        .collect();
+
+    let mut out = String::new();
+    if load_addr != PROGRAM_START {
+        out.push_str(&format!("    ORG {:#05X}\n", load_addr));
+    }
+    for (offset, instruction) in instructions(rom) {
+        let addr = load_addr + offset;
+        if labels.contains(&addr) {
+            out.push_str(&format!("L_{:04X}:\n", addr));
+        }
+
+        let text = match (instruction, target(instruction)) {
+            (Instruction::Jump(_), Some(to)) if labels.contains(&to) => format!("JP L_{:04X}", to),
+            (Instruction::Call(_), Some(to)) if labels.contains(&to) => {
+                format!("CALL L_{:04X}", to)
+            }
+            _ if offset + 1 == rom.len() => format!("DB {:#04X}", rom[offset]),
+            _ => disassemble_instruction(instruction),
+        };
+        let opcode = match rom.get(offset..offset + 2) {
+            Some(&[hi, lo]) => format!("{:02X}{:02X}", hi, lo),
+            _ => format!("{:02X}", rom[offset]),
+        };
+        out.push_str(&format!("    {:<20}; {:03X}: {}\n", text, addr, opcode));
+    }
+    out
+}
+
+// What a static trace of a ROM found: the addresses of the instructions it could reach,
+// the addresses ANNN points I at (most likely sprites or other data), and the BNNN jumps
+// whose targets depend on V0 and so couldn't be followed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReachabilityReport {
+    pub code: BTreeSet<usize>,
+    pub data: BTreeSet<usize>,
+    pub indirect_jumps: BTreeSet<usize>,
+}
+
+impl ReachabilityReport {
+    // whether the byte at `addr` is part of a reachable instruction
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.code.contains(&addr) || addr.checked_sub(1).is_some_and(|a| self.code.contains(&a))
+    }
+}
+
+// Trace a ROM loaded at PROGRAM_START from the instruction at `entry` without running
+// it. Jumps and calls are followed (a call also continues after itself, where the
+// subroutine returns to), either outcome of a skip is reachable, and the trace stops at
+// anything that ends the program or leaves the ROM. Whatever is never reached is
+// probably data.
+pub fn reachable_analysis(rom: &[u8], entry: usize) -> ReachabilityReport {
+    use Instruction as In;
+
+    let mut report = ReachabilityReport::default();
+    let mut pending = vec![entry];
+    while let Some(addr) = pending.pop() {
+        let Some(offset) = addr.checked_sub(PROGRAM_START) else {
+            continue;
+        };
+        let Some(&[hi, lo]) = rom.get(offset..offset + 2) else {
+            continue;
+        };
+        if !report.code.insert(addr) {
+            continue;
+        }
+
+        match decode(u16::from_be_bytes([hi, lo])) {
+            In::Halt | In::Exit | In::Return | In::SysCall(_) | In::Unknown(_) => {}
+            In::Jump(nnn) => pending.push(nnn as usize),
+            In::Call(nnn) => pending.extend([nnn as usize, addr + 2]),
+            In::JumpV0(_) => {
+                report.indirect_jumps.insert(addr);
+            }
+            In::SkipEqImm { .. }
+            | In::SkipNeImm { .. }
+            | In::SkipEqXY { .. }
+            | In::SkipNeXY { .. }
+            | In::SkipKeyPressed { .. }
+            | In::SkipKeyNotPressed { .. } => pending.extend([addr + 2, addr + 4]),
+            In::LoadIndex(nnn) => {
+                report.data.insert(nnn as usize);
+                pending.push(addr + 2);
+            }
+            _ => pending.push(addr + 2),
+        }
+    }
+    report
+}
+
+pub fn reachability_example() {
+    // A208: I = the sprite, D015: draw it, 1204: loop forever, then the sprite itself
+    let rom = [
+        0xA2, 0x08, 0xD0, 0x15, 0x12, 0x04, 0x00, 0x00, 0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    ];
+    let report = reachable_analysis(&rom, PROGRAM_START);
+
+    assert_eq!(report.code, BTreeSet::from([0x200, 0x202, 0x204]));
+    assert_eq!(report.data, BTreeSet::from([0x208]));
+    assert!((0x208..0x20D).all(|addr| !report.is_code(addr)));
+    println!("reachable: {:X?}, data: {:X?}", report.code, report.data);
+}
+
+// whether disassembling `rom` and assembling the result gives back the same bytes,
+// as a check on the assembler and disassembler agreeing with each other
+pub fn roundtrip_check(rom: &[u8]) -> bool {
+    assemble_text(&disassemble(rom)).is_ok_and(|bytes| bytes == rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{HaltReason, CPU};
+
+    #[test]
+    fn assembler_resolves_forward_and_backward_labels() {
+        let mut asm = Assembler::new();
+        asm.label("start")
+            .label_ref(Instruction::Call, "sub")
+            .label_ref(Instruction::Jump, "start")
+            .label("sub")
+            .instruction(Instruction::Return);
+        assert_eq!(asm.address(), 0x206);
+        assert_eq!(asm.finish(), Ok(vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]));
+    }
+
+    #[test]
+    fn assembler_errors() {
+        let mut asm = Assembler::new();
+        asm.label_ref(Instruction::Jump, "nowhere");
+        assert_eq!(
+            asm.finish(),
+            Err(AsmError::UndefinedLabel("nowhere".to_string()))
+        );
+
+        let mut asm = Assembler::new();
+        asm.label("twice").label("twice");
+        assert_eq!(
+            asm.finish(),
+            Err(AsmError::DuplicateLabel("twice".to_string()))
+        );
+
+        let mut asm = Assembler::with_origin(0xFFE);
+        asm.bytes(&[0, 0])
+            .label("end")
+            .label_ref(Instruction::Jump, "end");
+        assert_eq!(asm.finish(), Err(AsmError::AddressOutOfRange(0x1000)));
+    }
+
+    #[test]
+    fn assemble_text_syntax() {
+        let src = "
+            start:  LD V0, 5      ; comments are ignored
+                    add v0, V1
+                    SE V0, 0x0A
+                    JP start
+            data:   DB 0xF0, 0b1001, 144
+        ";
+        assert_eq!(
+            assemble_text(src),
+            Ok(vec![
+                0x60, 0x05, 0x80, 0x14, 0x30, 0x0A, 0x12, 0x00, 0xF0, 0x09, 0x90
+            ])
+        );
+    }
+
+    #[test]
+    fn assemble_text_errors() {
+        assert_eq!(
+            assemble_text("CLS\nMOV V0, V1"),
+            Err(AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: "MOV".to_string()
+            })
+        );
+        for bad in ["LD V0, 256", "DRW V0, V1, 16", "DB", "DB 300", "JP 0x1000"] {
+            assert!(
+                matches!(
+                    assemble_text(bad),
+                    Err(AsmError::InvalidOperands { line: 1, .. })
+                ),
+                "{} should have been rejected",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        // the two halves of cpu::example()'s program
+        assert!(roundtrip_check(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]));
+        assert!(roundtrip_check(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]));
+        assert!(roundtrip_check(&[0x00, 0xE0, 0x12]));
+        assert_eq!(disassemble(&[0x60, 0x05, 0xAB]), "LD V0, 0x05\nDB 0xAB\n");
+
+        // every opcode, defined or not, comes back out of the assembler unchanged
+        for opcode in 0..=0xFFFFu16 {
+            assert!(roundtrip_check(&opcode.to_be_bytes()), "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn instructions_decodes_the_example_program() {
+        // cpu::example()'s subroutine
+        let decoded: Vec<_> = instructions(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]).collect();
+        assert_eq!(
+            decoded,
+            vec![
+                (0, Instruction::AddXY { x: 0, y: 1 }),
+                (2, Instruction::AddXY { x: 0, y: 1 }),
+                (4, Instruction::Return),
+            ]
+        );
+
+        let decoded: Vec<_> = instructions(&[0x00, 0xE0, 0x12, 0x00, 0x80]).collect();
+        assert_eq!(
+            decoded,
+            vec![
+                (0, Instruction::ClearScreen),
+                (2, Instruction::Jump(0x200)),
+                (4, Instruction::Unknown(0x8000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_labels_jump_targets_inside_the_rom() {
+        // CALL 0x204, HALT, RET, JP 0x300
+        let rom = [0x22, 0x04, 0x00, 0x00, 0x00, 0xEE, 0x13, 0x00];
+        let text = listing(&rom, PROGRAM_START);
+        assert!(text.contains("L_0204:\n    RET"));
+        assert!(text.contains("CALL L_0204"));
+        assert!(text.contains("; 204: 00EE"));
+        // 0x300 is outside the ROM, so it stays a plain address
+        assert!(text.contains("JP 0x300"));
+        assert_eq!(text.matches("L_").count(), 2);
+        assert_eq!(assemble_text(&text), Ok(rom.to_vec()));
+    }
+
+    #[test]
+    fn examples_run() {
+        reachability_example();
+        parse_hex_example();
+        self_modification_example();
+    }
+
+    #[test]
+    fn reachability_follows_calls_and_skips() {
+        // 2206: call, 3000: skip one of the next two, 1208: jump, B300: indirect jump
+        let rom = [
+            0x22, 0x06, 0x30, 0x00, 0x12, 0x08, 0x00, 0xEE, 0xB3, 0x00, 0x00, 0x00,
+        ];
+        let report = reachable_analysis(&rom, PROGRAM_START);
+        assert_eq!(
+            report.code,
+            BTreeSet::from([0x200, 0x202, 0x204, 0x206, 0x208])
+        );
+        assert_eq!(report.indirect_jumps, BTreeSet::from([0x208]));
+        assert!(!report.is_code(0x20A));
+    }
+
+    #[test]
+    fn parse_hex_opcodes_errors() {
+        let err = parse_hex_opcodes("2100 80141").unwrap_err();
+        assert_eq!(err.to_string(), "opcode 1 '80141' isn't 4 hex digits long");
+        let err = parse_hex_opcodes("00E0\tzzzz").unwrap_err();
+        assert_eq!(err.to_string(), "opcode 1 'zzzz' isn't hexadecimal");
+        assert_eq!(
+            parse_hex_opcodes("00e0 ABcd"),
+            Ok(vec![0x00, 0xE0, 0xAB, 0xCD])
+        );
+    }
+
+    #[test]
+    fn self_modifying_rom_is_detected() {
+        // A202: I = 0x202, 00E0 twice, F355 at 0x206: store V0..=V3 over 0x202..=0x205,
+        // which is code that has already run
+        let rom = [0xA2, 0x02, 0x00, 0xE0, 0x00, 0xE0, 0xF3, 0x55];
+        assert!(detects_self_modification(&rom));
+
+        // A300: I = 0x300, F355: store into work RAM, 00E0, 1204: loop
+        let rom = [0xA3, 0x00, 0xF3, 0x55, 0x00, 0xE0, 0x12, 0x04];
+        assert!(!detects_self_modification(&rom));
+    }
+
+    #[test]
+    fn assembled_text_runs() {
+        let src = "
+                    LD V0, 5
+                    LD V1, 3
+            loop:   ADD V2, V0
+                    ADD V1, 0xFF    ; V1 -= 1
+                    SE V1, 0
+                    JP loop
+                    HALT
+        ";
+        let mut cpu = CPU::new();
+        cpu.load_rom(&assemble_text(src).unwrap()).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[2], 15);
+    }
+
+    #[test]
+    fn opcodes_test_rom_is_assembled_from_its_source() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms");
+        let src = std::fs::read_to_string(format!("{}/opcodes.asm", dir)).unwrap();
+        let rom = std::fs::read(format!("{}/opcodes.ch8", dir)).unwrap();
+        assert_eq!(assemble_text(&src), Ok(rom));
+    }
+
+    #[test]
+    fn listing_reassembles_at_any_load_address() {
+        // CALL 0x604, JP 0x600, RET, for loading at 0x600
+        let rom = [0x26, 0x04, 0x16, 0x00, 0x00, 0xEE];
+        let text = listing(&rom, 0x600);
+        assert!(text.starts_with("    ORG 0x600\n"));
+        assert!(text.contains("CALL L_0604") && text.contains("JP L_0600"));
+        assert_eq!(assemble_text(&text), Ok(rom.to_vec()));
+
+        assert!(!listing(&rom, PROGRAM_START).contains("ORG"));
+    }
+
+    #[test]
+    fn org_sets_the_origin_for_labels() {
+        assert_eq!(
+            assemble_text("ORG 0x600\nstart: JP start"),
+            Ok(vec![0x16, 0x00])
+        );
+        assert_eq!(
+            assemble_text("CLS\nORG 0x600"),
+            Err(AsmError::MisplacedOrigin { line: 2 })
+        );
+        assert_eq!(
+            assemble_text("start:\nORG 0x600"),
+            Err(AsmError::MisplacedOrigin { line: 2 })
+        );
+        assert!(matches!(
+            assemble_text("ORG 0x1000"),
+            Err(AsmError::InvalidOperands { line: 1, .. })
+        ));
+    }
+}
@@ -14,17 +14,60 @@ pub fn int_vs_int() {
     println!("b: {:016b} {}", b, b);
 }
 
+// The same idea for integers of any width up to 64 bits: the boundary values of a
+// `width`-bit pattern, each paired with what it means as a signed integer. Up to the
+// largest positive signed value the two agree; from the point where the top (sign) bit
+// gets set, the signed reading is 2^width less, down to -1 for all ones.
+pub fn signed_unsigned_table(width: u32) -> Vec<(u64, i64)> {
+    assert!((1..=64).contains(&width), "width must be 1 to 64 bits");
+    let max_unsigned = u64::MAX >> (64 - width);
+    let sign_bit = 1 << (width - 1);
+
+    // shifting the sign bit up to bit 63 and back down again copies it into every bit
+    // above the width, which is sign extension
+    let as_signed = |bits: u64| ((bits << (64 - width)) as i64) >> (64 - width);
+    let mut values = vec![0, 1, sign_bit - 1, sign_bit, max_unsigned];
+    // at small widths some of these are the same value
+    values.sort();
+    values.dedup();
+    values
+        .into_iter()
+        .map(|bits| (bits, as_signed(bits)))
+        .collect()
+}
+
+pub fn signed_unsigned_table_demo() {
+    for width in [8, 16, 32] {
+        println!("{} bits:", width);
+        for (unsigned, signed) in signed_unsigned_table(width) {
+            println!("  {:>10} as signed is {}", unsigned, signed);
+        }
+    }
+
+    let table = signed_unsigned_table(8);
+    assert_eq!(
+        table,
+        vec![(0, 0), (1, 1), (127, 127), (128, -128), (255, -1)]
+    );
+    assert!(table
+        .iter()
+        .all(|&(unsigned, signed)| unsigned as u8 as i8 as i64 == signed));
+}
+
 // ints and floating points are also just different encodings of bit
 // patterns: here an f32 bit pattern gets copied and interpreted as a u32,
 // resulting in a different value.
+// (f32::to_bits and f32::from_bits do the same thing safely; transmute is used here
+// because it is the raw reinterpretation being demonstrated.)
+#[allow(unnecessary_transmutes)]
 pub fn f32_as_u32() {
     let a: f32 = 42.42;
-    let b: u32 = unsafe { std::mem::transmute(a) };
+    let b = unsafe { std::mem::transmute::<f32, u32>(a) };
 
     println!("{:032b}", b);
     println!("{}", b);
 
-    let c = unsafe { std::mem::transmute(b) };
+    let c = unsafe { std::mem::transmute::<u32, f32>(b) };
     println!("{}", c);
     assert_eq!(a, c);
 }
@@ -44,35 +87,296 @@ pub fn twos_complement() {
     println!("-a: {:032b} {}", neg_a, neg_a);
 }
 
+// Adding two signed integers can only OVERFLOW when they have the same sign: a positive
+// and a negative number always sum to something between them. In twos complement an
+// overflowing sum wraps around by exactly 2^32, which always flips its sign bit, so two
+// positives appear to sum to a negative and two negatives to a positive. The overflow
+// check is then just: the operands' sign bits agree, and the wrapped result's differs.
+// a ^ sum and b ^ sum have their top bit set when the result's sign differs from
+// that operand's, so both being negative says exactly that.
+pub fn add_would_overflow_signed(a: i32, b: i32) -> bool {
+    let sum = a.wrapping_add(b);
+    (a ^ sum) & (b ^ sum) < 0
+}
+
+pub fn signed_overflow_demo() {
+    let cases = [
+        (i32::MAX, 1),
+        (5, 3),
+        (-1, i32::MIN),
+        (i32::MIN, i32::MAX),
+        (-5, -3),
+    ];
+    for (a, b) in cases {
+        let overflows = add_would_overflow_signed(a, b);
+        println!("{} + {} overflows: {}", a, b, overflows);
+        assert_eq!(overflows, a.checked_add(b).is_none());
+    }
+    assert!(add_would_overflow_signed(i32::MAX, 1));
+    assert!(!add_would_overflow_signed(5, 3));
+    assert!(add_would_overflow_signed(-1, i32::MIN));
+}
+
+// Converting to binary by hand is REPEATED DIVISION: divide by 2, note the remainder,
+// and carry on with the quotient until it reaches 0. The remainders are the bits from
+// least to most significant, so the binary string is them read backwards. Returns the
+// string and each (quotient, remainder) along the way; 0 takes no steps and is "0".
+pub fn to_binary_steps(mut n: u32) -> (String, Vec<(u32, u32)>) {
+    let mut steps = Vec::new();
+    while n > 0 {
+        steps.push((n / 2, n % 2));
+        n /= 2;
+    }
+
+    let binary: String = steps
+        .iter()
+        .rev()
+        .map(|&(_, bit)| if bit == 1 { '1' } else { '0' })
+        .collect();
+    if binary.is_empty() {
+        ("0".to_string(), steps)
+    } else {
+        (binary, steps)
+    }
+}
+
+// The way back is DOUBLING: start from 0 and, for each bit from the most significant,
+// double the total and add the bit. Returns the value and each (bit, total so far), or
+// None if `binary` has anything other than 0s and 1s or doesn't fit in a u32.
+pub fn from_binary_steps(binary: &str) -> Option<(u32, Vec<(u32, u32)>)> {
+    if binary.is_empty() {
+        return None;
+    }
+
+    let mut total: u32 = 0;
+    let mut steps = Vec::new();
+    for c in binary.chars() {
+        let bit = c.to_digit(2)?;
+        total = total.checked_mul(2)?.checked_add(bit)?;
+        steps.push((bit, total));
+    }
+    Some((total, steps))
+}
+
+pub fn binary_steps_demo() {
+    let (binary, steps) = to_binary_steps(13);
+    for (quotient, remainder) in &steps {
+        println!("-> {:>2} remainder {}", quotient, remainder);
+    }
+    println!("13 is {}", binary);
+    assert_eq!(binary, "1101");
+    assert_eq!(steps, vec![(6, 1), (3, 0), (1, 1), (0, 1)]);
+
+    assert_eq!(
+        from_binary_steps("1101"),
+        Some((13, vec![(1, 1), (1, 3), (0, 6), (1, 13)]))
+    );
+    assert_eq!(to_binary_steps(0), ("0".to_string(), vec![]));
+    assert_eq!(from_binary_steps("102"), None);
+    assert_eq!(from_binary_steps(&"1".repeat(33)), None);
+}
+
 // Unlike floating point numbers, ints cannot sacrifice precision to
 // extend their range. When an int goes above its upper bound it will
 // overflow to its lower bound, and vice versa when it goes below its
 // lower bound. This is called INTEGER OVERFLOW. For example, a u16
 // can represent values 0 to 65,535 inclusive; going above or below this
-// range will loop to the other end. Compile with -O flag (which means
-// optimised), otherwise program panics.
-#[allow(arithmetic_overflow)]
+// range will loop to the other end. Plain `+` and `-` only wrap like this in
+// release builds (debug builds panic instead, see overflow_panics_in_debug), so the
+// examples ask for the wrapping explicitly with wrapping_add and wrapping_sub, which
+// behave the same whatever the build. checked_add is the alternative that reports the
+// overflow instead of wrapping.
 pub fn int_overflow() {
     println!("Example 1: u16");
-    let _0: u16 = 0b0000_0000_0000_0000;
-    let _1: u16 = 0b0000_0000_0000_0001;
-    let _2: u16 = 0b0000_0000_0000_0011;
-    let _65533: u16 = 0b1111_1111_1111_1100;
-    let _65534: u16 = 0b1111_1111_1111_1101;
-    let _65535: u16 = 0b1111_1111_1111_1111;
-    print!("{}, {}, {}, ... , ", _0, _1, _2);
-    println!("{}, {}, {}, ...", _65533, _65534, _65535);
-    println!("{} + {} = {}", _1, _65535, _1 + _65535);
+    let zero: u16 = 0b0000_0000_0000_0000;
+    let one: u16 = 0b0000_0000_0000_0001;
+    let two: u16 = 0b0000_0000_0000_0010;
+    let max_minus_2: u16 = 0b1111_1111_1111_1101;
+    let max_minus_1: u16 = 0b1111_1111_1111_1110;
+    let max: u16 = 0b1111_1111_1111_1111;
+    print!("{}, {}, {}, ... , ", zero, one, two);
+    println!("{}, {}, {}, ...", max_minus_2, max_minus_1, max);
+    println!("{} + {} = {}", one, max, one.wrapping_add(max));
+    assert_eq!(max, u16::MAX);
+    assert_eq!(one.wrapping_add(max), 0);
 
     println!("\nExample 2: u8");
-    let (a, b) = (200, 200);
-    let c: u8 = a + b;
+    let (a, b): (u8, u8) = (200, 200);
+    let c = a.wrapping_add(b);
     println!("{} + {} = {}", a, b, c);
+    println!("{} checked_add {} = {:?}", a, b, a.checked_add(b));
+    assert_eq!(c, 144);
+    assert_eq!(a.checked_add(b), None);
 
     println!("\nExample 3: i8");
     let (a, b): (i8, i8) = (-128, 127);
-    println!("{} - 1 = {}", a, a - 1);
-    println!("{} + 1 = {}", b, b + 1);
+    println!("{} - 1 = {}", a, a.wrapping_sub(1));
+    println!("{} + 1 = {}", b, b.wrapping_add(1));
+    assert_eq!(a.wrapping_sub(1), 127);
+    assert_eq!(b.wrapping_add(1), -128);
+}
+
+// The same overflow as Example 2 above, with a plain `+`. Debug builds check every
+// arithmetic operation and panic with "attempt to add with overflow"; release builds
+// leave the check out and silently wrap to 144. The operands go through black_box so
+// the compiler can't spot the overflow at compile time and refuse to build it.
+pub fn overflow_panics_in_debug() -> u8 {
+    let (a, b): (u8, u8) = std::hint::black_box((200, 200));
+    a + b
+}
+
+// Every way of doing an operation that might overflow, side by side: WRAPPING around to
+// the other end of the range, CHECKED (None on overflow), SATURATING at the bound that
+// was crossed, and OVERFLOWING (the wrapped result plus whether it wrapped).
+//
+// PrimInt is the handful of integer methods this needs, so arithmetic_report can be
+// written once for every primitive integer type.
+pub trait PrimInt: Copy + std::fmt::Debug + PartialEq {
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn saturating_add(self, rhs: Self) -> Self;
+    fn overflowing_add(self, rhs: Self) -> (Self, bool);
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    fn saturating_sub(self, rhs: Self) -> Self;
+    fn overflowing_sub(self, rhs: Self) -> (Self, bool);
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    fn saturating_mul(self, rhs: Self) -> Self;
+    fn overflowing_mul(self, rhs: Self) -> (Self, bool);
+}
+
+macro_rules! impl_prim_int {
+    ($($t:ty),*) => {$(
+        impl PrimInt for $t {
+            fn wrapping_add(self, rhs: Self) -> Self { <$t>::wrapping_add(self, rhs) }
+            fn checked_add(self, rhs: Self) -> Option<Self> { <$t>::checked_add(self, rhs) }
+            fn saturating_add(self, rhs: Self) -> Self { <$t>::saturating_add(self, rhs) }
+            fn overflowing_add(self, rhs: Self) -> (Self, bool) { <$t>::overflowing_add(self, rhs) }
+            fn wrapping_sub(self, rhs: Self) -> Self { <$t>::wrapping_sub(self, rhs) }
+            fn checked_sub(self, rhs: Self) -> Option<Self> { <$t>::checked_sub(self, rhs) }
+            fn saturating_sub(self, rhs: Self) -> Self { <$t>::saturating_sub(self, rhs) }
+            fn overflowing_sub(self, rhs: Self) -> (Self, bool) { <$t>::overflowing_sub(self, rhs) }
+            fn wrapping_mul(self, rhs: Self) -> Self { <$t>::wrapping_mul(self, rhs) }
+            fn checked_mul(self, rhs: Self) -> Option<Self> { <$t>::checked_mul(self, rhs) }
+            fn saturating_mul(self, rhs: Self) -> Self { <$t>::saturating_mul(self, rhs) }
+            fn overflowing_mul(self, rhs: Self) -> (Self, bool) { <$t>::overflowing_mul(self, rhs) }
+        }
+    )*};
+}
+
+impl_prim_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpReport<T> {
+    pub wrapping: T,
+    pub checked: Option<T>,
+    pub saturating: T,
+    pub overflowing: (T, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArithReport<T> {
+    pub add: OpReport<T>,
+    pub sub: OpReport<T>,
+    pub mul: OpReport<T>,
+}
+
+pub fn arithmetic_report<T: PrimInt>(a: T, b: T) -> ArithReport<T> {
+    ArithReport {
+        add: OpReport {
+            wrapping: a.wrapping_add(b),
+            checked: a.checked_add(b),
+            saturating: a.saturating_add(b),
+            overflowing: a.overflowing_add(b),
+        },
+        sub: OpReport {
+            wrapping: a.wrapping_sub(b),
+            checked: a.checked_sub(b),
+            saturating: a.saturating_sub(b),
+            overflowing: a.overflowing_sub(b),
+        },
+        mul: OpReport {
+            wrapping: a.wrapping_mul(b),
+            checked: a.checked_mul(b),
+            saturating: a.saturating_mul(b),
+            overflowing: a.overflowing_mul(b),
+        },
+    }
+}
+
+pub fn arithmetic_report_demo() {
+    // 200 + 100 and 200 * 100 are too big for a u8, but 200 - 100 is fine...
+    let report = arithmetic_report(200u8, 100);
+    println!("200u8 op 100u8: {:#?}", report);
+    assert_eq!(report.add.saturating, 255);
+    assert_eq!(report.add.overflowing, (44, true));
+    assert_eq!(report.sub.checked, Some(100));
+    assert_eq!(report.mul.checked, None);
+    assert_eq!(report.mul.wrapping, 32); // 20000 % 256
+
+    // ...while 100 - 200 wraps around below 0
+    let report = arithmetic_report(100u8, 200);
+    assert_eq!(report.sub.wrapping, 156);
+    assert_eq!(report.sub.saturating, 0);
+}
+
+// `as` never fails, so every cast has to produce something, even when the value doesn't
+// fit. Between integers, NARROWING keeps only the low bits (300 is 0b1_0010_1100, and
+// the low 8 of those are 44) and casting between signed and unsigned of the same size
+// keeps the bits as they are, reinterpreting them in the other encoding. WIDENING
+// extends with copies of the sign bit for signed sources and with zeros for unsigned
+// ones, so the value is always kept. Floats are truncated toward zero and then
+// SATURATED to the target's range, with NaN going to 0; before Rust 1.45 out of range
+// float casts were undefined behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CastResults {
+    pub u32_300_as_u8: u8,
+    pub u32_256_as_u8: u8,
+    pub i32_minus_1_as_u32: u32,
+    pub i8_minus_1_as_i32: i32,
+    pub i8_minus_1_as_u32: u32,
+    pub u8_255_as_i32: i32,
+    pub f32_3_9_as_i32: i32,
+    pub f32_minus_3_9_as_i32: i32,
+    pub f32_300_as_u8: u8,
+    pub f32_minus_1_as_u8: u8,
+    pub f32_nan_as_i32: i32,
+    pub f32_1e10_as_i32: i32,
+}
+
+#[allow(clippy::cast_nan_to_int)]
+pub fn cast_demo() -> CastResults {
+    let results = CastResults {
+        u32_300_as_u8: 300u32 as u8,
+        u32_256_as_u8: 256u32 as u8,
+        i32_minus_1_as_u32: -1i32 as u32,
+        i8_minus_1_as_i32: -1i8 as i32,
+        i8_minus_1_as_u32: -1i8 as u32,
+        u8_255_as_i32: 255u8 as i32,
+        f32_3_9_as_i32: 3.9f32 as i32,
+        f32_minus_3_9_as_i32: -3.9f32 as i32,
+        f32_300_as_u8: 300f32 as u8,
+        f32_minus_1_as_u8: -1f32 as u8,
+        f32_nan_as_i32: f32::NAN as i32,
+        f32_1e10_as_i32: 1e10f32 as i32,
+    };
+    println!("{:#?}", results);
+
+    assert_eq!(results.u32_300_as_u8, 44);
+    assert_eq!(results.u32_256_as_u8, 0);
+    assert_eq!(results.i32_minus_1_as_u32, u32::MAX);
+    assert_eq!(results.i8_minus_1_as_i32, -1);
+    // sign extended to 32 ones first, then reinterpreted as unsigned
+    assert_eq!(results.i8_minus_1_as_u32, u32::MAX);
+    assert_eq!(results.u8_255_as_i32, 255);
+    assert_eq!(results.f32_3_9_as_i32, 3);
+    assert_eq!(results.f32_minus_3_9_as_i32, -3);
+    assert_eq!(results.f32_300_as_u8, u8::MAX);
+    assert_eq!(results.f32_minus_1_as_u8, 0);
+    assert_eq!(results.f32_nan_as_i32, 0);
+    assert_eq!(results.f32_1e10_as_i32, i32::MAX);
+    results
 }
 
 // ENDIANNESS refers to the order in which systems represent multibyte sequences
@@ -94,6 +398,122 @@ pub fn endianness() {
     println!("little endian: {:?}", c.to_le_bytes());
 }
 
+// The 32 bits of `x` in groups of four, under a ruler numbering the first bit of each
+// group (bit 31 is the most significant):
+//
+//     31   27   23   19   15   11   7    3
+//     0100 0010 0010 1001 1010 1110 0001 0100
+//
+// For an f32 that puts the sign at 31, the exponent at 30 to 23 and the mantissa below.
+pub fn labeled_bits_u32(x: u32) -> String {
+    let mut ruler = String::new();
+    let mut bits = String::new();
+    for nibble in (0..8).rev() {
+        let high_bit = nibble * 4 + 3;
+        ruler.push_str(&format!("{:<5}", high_bit));
+        bits.push_str(&format!("{:04b} ", (x >> (nibble * 4)) & 0xf));
+    }
+    format!("{}\n{}", ruler.trim_end(), bits.trim_end())
+}
+
+// Binary file formats say which byte order their multibyte fields are in, and it isn't
+// always the native one (network protocols, for example, are big endian). ByteReader
+// walks through a buffer reading fields in whichever order is asked for, rather than
+// the machine's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+// a read ran past the end of the buffer; nothing is consumed by a failed read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBytes {
+    pub position: usize,
+    pub wanted: usize,
+}
+
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], OutOfBytes> {
+        let out_of_bytes = OutOfBytes {
+            position: self.position,
+            wanted: N,
+        };
+        let field = self.bytes[self.position..]
+            .first_chunk::<N>()
+            .ok_or(out_of_bytes)?;
+        self.position += N;
+        Ok(*field)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, OutOfBytes> {
+        let [byte] = self.take()?;
+        Ok(byte)
+    }
+
+    pub fn read_u16(&mut self, order: ByteOrder) -> Result<u16, OutOfBytes> {
+        let bytes = self.take()?;
+        Ok(match order {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        })
+    }
+
+    pub fn read_u32(&mut self, order: ByteOrder) -> Result<u32, OutOfBytes> {
+        let bytes = self.take()?;
+        Ok(match order {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        })
+    }
+
+    // an f32 is read as the u32 with the same bits, so byte order applies the same way
+    pub fn read_f32(&mut self, order: ByteOrder) -> Result<f32, OutOfBytes> {
+        self.read_u32(order).map(f32::from_bits)
+    }
+}
+
+pub fn byte_reader_demo() {
+    let buffer = [
+        0x2a, // u8
+        0xaa, 0xbb, // u16, big endian
+        0xdd, 0xcc, 0xbb, 0xaa, // u32, little endian
+        0x40, 0x49, 0x0f, 0xdb, // f32, big endian
+    ];
+    let mut reader = ByteReader::new(&buffer);
+
+    assert_eq!(reader.read_u8(), Ok(42));
+    assert_eq!(reader.read_u16(ByteOrder::Big), Ok(0xaabb));
+    assert_eq!(reader.read_u32(ByteOrder::Little), Ok(0xaabbccdd));
+    assert_eq!(reader.read_f32(ByteOrder::Big), Ok(std::f32::consts::PI));
+    assert_eq!(reader.position(), buffer.len());
+    assert_eq!(
+        reader.read_u8(),
+        Err(OutOfBytes {
+            position: 11,
+            wanted: 1
+        })
+    );
+    println!("read {} bytes", reader.position());
+}
+
 // When represented in scientific notation FLOATING POINT NUMBERS, like 2.498 x 10^18
 // have 4 components: the SIGN, which indicates whether it is positive or
 // negative; the MANTISSA, which is the value (here 2.498); the RADIX, which is the
@@ -105,6 +525,7 @@ pub fn endianness() {
 pub fn floating_point_deconstruction(n: f32) {
     // reinterpret f32 as 32 bits
     let n_bits: u32 = n.to_bits();
+    println!("{}\n", labeled_bits_u32(n_bits));
 
     // separate 32 bits of f32 into its components:
     let sign_ = (n_bits >> 31) & 1; // shift 31 bits then 1-bit AND mask
@@ -139,8 +560,52 @@ pub fn floating_point_deconstruction(n: f32) {
     println!("mantissa | {:023b} | {}", fraction, mantissa);
 }
 
+// The bits of an f32 split into its three fields, with a key underneath and what each
+// field decodes to:
+//
+//     0|10000100|01010011010111000010100
+//     S|EEEEEEEE|MMMMMMMMMMMMMMMMMMMMMMM
+//     sign     = 0 (+)
+//     exponent = 132 - 127 = 5
+//     mantissa = 1.01010011010111000010100 (binary) = 1.325624942779541
+//
+// An exponent field of 0 marks zero or a subnormal, whose mantissa has no implicit
+// leading 1 and whose exponent is fixed at -126; one of all 1s marks infinity or NaN.
+pub fn visualize_f32_layout(x: f32) -> String {
+    let bits = x.to_bits();
+    let sign_ = bits >> 31;
+    let exponent_ = (bits >> 23) & 0xff;
+    let fraction = bits & 0x7fffff;
+
+    let exponent = match exponent_ {
+        0 => "0 (zero or subnormal) = -126".to_string(),
+        0xff => "255 (infinity or NaN)".to_string(),
+        _ => format!("{} - 127 = {}", exponent_, exponent_ as i32 - 127),
+    };
+    let leading = if exponent_ == 0 { 0 } else { 1 };
+    let mantissa = leading as f64 + fraction as f64 / (1 << 23) as f64;
+
+    format!(
+        "{:01b}|{:08b}|{:023b}\n\
+         S|EEEEEEEE|MMMMMMMMMMMMMMMMMMMMMMM\n\
+         sign     = {} ({})\n\
+         exponent = {}\n\
+         mantissa = {}.{:023b} (binary) = {}",
+        sign_,
+        exponent_,
+        fraction,
+        sign_,
+        if sign_ == 1 { '-' } else { '+' },
+        exponent,
+        leading,
+        fraction,
+        mantissa
+    )
+}
+
 // In Rust, f64 and f32 only implement the PartialEq trait and not Eq,
 // as these types include values for which == is not mathematically valid.
+#[allow(clippy::eq_op)] // comparing NAN with itself is the point
 pub fn floating_point_partial_eq() {
     // Some f32/f64 values with different bit patterns are treated as equal:
     let m: f32 = -0.0;
@@ -153,7 +618,8 @@ pub fn floating_point_partial_eq() {
     );
 
     // And some with the same bit pattern are treated as unequal:
-    assert!(f32::NAN != f32::NAN);
+    let nan = f32::NAN;
+    assert!(nan != nan);
     println!(
         "\nNAN != NAN\n   {:032b}\n!= {:032b}",
         f32::NAN.to_bits(),
@@ -163,3 +629,769 @@ pub fn floating_point_partial_eq() {
     // ever equal, even if they really do have the same bit pattern, which is
     // what is being shown here.)
 }
+
+// IEEE floats have a SIGNED ZERO: 0.0 and -0.0 differ only in the sign bit. They are
+// equal under ==, but not the same value, since the sign survives through arithmetic,
+// most visibly when dividing by them. -0.0 comes from results that are negative but
+// too small to represent (like -1e-30 * 1e-30 in f32) and from negating 0.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignedZeroReport {
+    pub positive_bits: u32,
+    pub negative_bits: u32,
+    pub compare_equal: bool,
+    pub bits_differ: bool,
+    pub one_over_positive: f32,
+    pub one_over_negative: f32,
+}
+
+pub fn signed_zero_demo() -> SignedZeroReport {
+    let positive = 0.0_f32;
+    let negative = -0.0_f32;
+
+    let report = SignedZeroReport {
+        positive_bits: positive.to_bits(),
+        negative_bits: negative.to_bits(),
+        compare_equal: positive == negative,
+        bits_differ: positive.to_bits() != negative.to_bits(),
+        one_over_positive: 1.0 / positive,
+        one_over_negative: 1.0 / negative,
+    };
+    println!("{:#?}", report);
+
+    assert!(report.compare_equal && report.bits_differ);
+    assert_eq!(report.negative_bits, 1 << 31);
+    assert_eq!(
+        (report.one_over_positive, report.one_over_negative),
+        (f32::INFINITY, f32::NEG_INFINITY)
+    );
+    report
+}
+
+// Float addition isn't ASSOCIATIVE: every intermediate result is rounded to the nearest
+// representable value, so the grouping decides what gets rounded away. 1e16 is past
+// 2^53, where f64 runs out of mantissa bits and neighbouring values are 2 apart, so
+// adding 1 to -1e16 first loses it completely, while cancelling the big values first
+// keeps it. Returns ((a + b) + c, a + (b + c)) and whether they came out equal.
+pub fn associativity_demo() -> (f64, f64, bool) {
+    let (a, b, c) = (1e16, -1e16, 1.0);
+    let left = (a + b) + c;
+    let right = a + (b + c);
+
+    println!("({:e} + {:e}) + {} = {}", a, b, c, left);
+    println!("{:e} + ({:e} + {}) = {}", a, b, c, right);
+    assert_eq!((left, right), (1.0, 0.0));
+    (left, right, left == right)
+}
+
+// Adding up a long list of floats loses the low bits of each value that are below the
+// precision of the running total. KAHAN SUMMATION keeps a COMPENSATION term with the
+// part of the last addition that was rounded off, and feeds it back into the next one,
+// so the error stays about the size of a single rounding instead of growing with the
+// length of the list.
+pub fn naive_sum(values: &[f32]) -> f32 {
+    values.iter().fold(0.0, |total, &x| total + x)
+}
+
+pub fn kahan_sum(values: &[f32]) -> f32 {
+    let mut total = 0.0_f32;
+    let mut compensation = 0.0_f32;
+    for &x in values {
+        let y = x - compensation;
+        let t = total + y;
+        // (t - total) is what actually got added; y minus that is what was lost
+        compensation = (t - total) - y;
+        total = t;
+    }
+    total
+}
+
+pub fn kahan_demo() {
+    // 1e-8 is below half of f32::EPSILON, so on its own it can't change a total of 1
+    let mut values = vec![1.0_f32];
+    values.extend(std::iter::repeat_n(1e-8_f32, 10_000));
+    let exact: f64 = values.iter().map(|&x| x as f64).sum();
+
+    let naive = naive_sum(&values);
+    let kahan = kahan_sum(&values);
+    println!("exact {}, naive {}, kahan {}", exact, naive, kahan);
+    assert_eq!(naive, 1.0);
+    assert!((kahan as f64 - exact).abs() < (naive as f64 - exact).abs());
+}
+
+// CATASTROPHIC CANCELLATION: subtracting two nearly equal numbers cancels out their
+// leading digits, leaving only the trailing ones, which are where the rounding error
+// is. For a small x, cos(x) is so close to 1 that it rounds to a value with only a few
+// meaningful bits below 1 (or to 1 itself), and 1 - cos(x) is then mostly or entirely
+// error. The identity 1 - cos(x) = 2 sin^2(x / 2) gets the same value without any
+// subtraction. Returns (naive, stable).
+pub fn cancellation_demo(x: f64) -> (f64, f64) {
+    let naive = 1.0 - x.cos();
+    let stable = 2.0 * (x / 2.0).sin().powi(2);
+    (naive, stable)
+}
+
+pub fn cancellation_examples() {
+    for x in [1e-1, 1e-4, 1e-8] {
+        let (naive, stable) = cancellation_demo(x);
+        println!("1 - cos({:e}): naive {:e}, stable {:e}", x, naive, stable);
+    }
+
+    // for tiny x, 1 - cos(x) is x^2 / 2 to far more digits than f64 has
+    let x = 1e-8;
+    let expected = x * x / 2.0;
+    let (naive, stable) = cancellation_demo(x);
+    assert_eq!(naive, 0.0);
+    assert!(((stable - expected) / expected).abs() < 1e-15);
+}
+
+// The x87 EXTENDED PRECISION format used by Intel FPUs packs 80 bits into a 1-bit sign,
+// a 15-bit exponent (bias 16383) and a 64-bit mantissa. Unlike f32 and f64, the leading
+// 1 of the mantissa isn't implicit: it is stored as an explicit INTEGER BIT (bit 63),
+// leaving 63 bits for the fraction. That makes it possible to write down "unnormal"
+// bit patterns, with an exponent but no integer bit, which IEEE single and double
+// precision simply have no way to express. In memory the 10 bytes are little endian,
+// so the mantissa comes first and the sign and exponent are in the last two bytes.
+pub const F80_EXPONENT_BIAS: i32 = 16383;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedFloatParts {
+    pub sign: u8,
+    pub exponent: u16, // biased
+    pub integer_bit: bool,
+    pub fraction: u64, // low 63 bits of the mantissa
+}
+
+impl ExtendedFloatParts {
+    pub fn unbiased_exponent(&self) -> i32 {
+        self.exponent as i32 - F80_EXPONENT_BIAS
+    }
+}
+
+pub fn decompose_f80(bytes: [u8; 10]) -> ExtendedFloatParts {
+    let mut mantissa_bytes = [0; 8];
+    mantissa_bytes.copy_from_slice(&bytes[..8]);
+    let mantissa = u64::from_le_bytes(mantissa_bytes);
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+
+    ExtendedFloatParts {
+        sign: (sign_exponent >> 15) as u8,
+        exponent: sign_exponent & 0x7fff, // 15-bit AND mask
+        integer_bit: mantissa >> 63 == 1,
+        fraction: mantissa & !(1 << 63), // everything but the integer bit
+    }
+}
+
+pub fn f80_deconstruction() {
+    // 1.0 is 1.0 x 2^0, so the exponent is just the bias and only the integer bit is set
+    let one = [0, 0, 0, 0, 0, 0, 0, 0x80, 0xff, 0x3f];
+    // 2.0 is 1.0 x 2^1, the same mantissa with the exponent one higher
+    let two = [0, 0, 0, 0, 0, 0, 0, 0x80, 0x00, 0x40];
+
+    for (name, bytes) in [("1.0", one), ("2.0", two)] {
+        let parts = decompose_f80(bytes);
+        println!(
+            "{}: {:?}, exponent 2^{}",
+            name,
+            parts,
+            parts.unbiased_exponent()
+        );
+        assert!(parts.integer_bit);
+        assert_eq!(parts.fraction, 0);
+    }
+    assert_eq!(decompose_f80(one).unbiased_exponent(), 0);
+    assert_eq!(decompose_f80(two).unbiased_exponent(), 1);
+}
+
+// Every finite float is exactly MANTISSA x 2^EXPONENT for some integer mantissa, so it
+// has an exact decimal expansion, and because 2^-k = 5^k / 10^k that expansion always
+// terminates. Printing a float only shows the shortest decimal that rounds back to the
+// same bits: 0.1_f32 prints as 0.1, but the value actually stored is the nearest f32
+// to 0.1, which is 0.100000001490116119384765625.
+//
+// The expansion is worked out with a little base-10 bignum (one digit per element,
+// least significant first): multiply the mantissa by 2 for each positive power of two,
+// or by 5 for each negative one and then shift the decimal point left by as many places.
+pub fn exact_decimal(x: f32) -> String {
+    if x.is_nan() {
+        return "NaN".to_string();
+    }
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if x.is_infinite() {
+        return format!("{}inf", sign);
+    }
+
+    let bits = x.to_bits();
+    let exponent_ = ((bits >> 23) & 0xff) as i32;
+    let fraction = bits & 0x7fffff;
+
+    // SUBNORMALS (exponent field 0) have no implicit leading 1 and share the exponent of
+    // the smallest normal numbers. Either way, the 23 fraction bits shift the exponent.
+    let (mantissa, exponent) = match exponent_ {
+        0 => (fraction, -126 - 23),
+        _ => (fraction | 1 << 23, exponent_ - 127 - 23),
+    };
+
+    let mut digits: Vec<u32> = mantissa
+        .to_string()
+        .bytes()
+        .rev()
+        .map(|b| (b - b'0') as u32)
+        .collect();
+    let multiply = |digits: &mut Vec<u32>, by: u32| {
+        let mut carry = 0;
+        for digit in digits.iter_mut() {
+            let product = *digit * by + carry;
+            *digit = product % 10;
+            carry = product / 10;
+        }
+        while carry > 0 {
+            digits.push(carry % 10);
+            carry /= 10;
+        }
+    };
+
+    let decimal_places = if exponent >= 0 {
+        (0..exponent).for_each(|_| multiply(&mut digits, 2));
+        0
+    } else {
+        (0..-exponent).for_each(|_| multiply(&mut digits, 5));
+        -exponent as usize
+    };
+    // pad with leading zeros so there's always at least one digit before the point
+    digits.resize(digits.len().max(decimal_places + 1), 0);
+
+    let digits: String = digits
+        .iter()
+        .rev()
+        .map(|&d| char::from(b'0' + d as u8))
+        .collect();
+    let (integer_part, fractional_part) = digits.split_at(digits.len() - decimal_places);
+    let fractional_part = fractional_part.trim_end_matches('0');
+
+    if fractional_part.is_empty() {
+        format!("{}{}", sign, integer_part)
+    } else {
+        format!("{}{}.{}", sign, integer_part, fractional_part)
+    }
+}
+
+pub fn exact_decimal_demo() {
+    for x in [0.5, 0.1, 1.0 / 3.0, 16777217.0, f32::MIN_POSITIVE] {
+        println!("{:>14e} is exactly {}", x, exact_decimal(x));
+    }
+    assert_eq!(exact_decimal(0.5), "0.5");
+    assert_eq!(exact_decimal(0.1), "0.100000001490116119384765625");
+}
+
+// FREXP splits a float into a mantissa in [0.5, 1.0) and a power of two, and LDEXP puts
+// them back together, mantissa x 2^exp. Both only need to move the exponent field: the
+// fraction bits stay exactly where they are. Zero, infinity and NaN have no meaningful
+// exponent and are passed through unchanged (with an exponent of 0 from frexp).
+pub fn frexp(x: f32) -> (f32, i32) {
+    if x == 0.0 || !x.is_finite() {
+        return (x, 0);
+    }
+
+    let bits = x.to_bits();
+    let sign = bits & 1 << 31;
+    let exponent_ = ((bits >> 23) & 0xff) as i32;
+    let fraction = bits & 0x7fffff;
+
+    // a subnormal's leading 1 is somewhere inside the fraction, so shift it up into the
+    // implicit bit position (dropping it) and lower the exponent to match
+    let (exponent_, fraction) = match exponent_ {
+        0 => {
+            let leading_one = 31 - fraction.leading_zeros() as i32;
+            let shift = 23 - leading_one;
+            (1 - shift, (fraction << shift) & 0x7fffff)
+        }
+        _ => (exponent_, fraction),
+    };
+
+    // an exponent field of 126 means 2^-1, putting 1.fraction x 2^-1 in [0.5, 1.0)
+    let mantissa = f32::from_bits(sign | 126 << 23 | fraction);
+    (mantissa, exponent_ - 126)
+}
+
+pub fn ldexp(mantissa: f32, exp: i32) -> f32 {
+    if mantissa == 0.0 || !mantissa.is_finite() {
+        return mantissa;
+    }
+
+    let (normalized, exponent) = frexp(mantissa);
+    let bits = normalized.to_bits();
+    let sign = bits & 1 << 31;
+    let fraction = bits & 0x7fffff;
+
+    // saturating rather than wrapping, so an exp near i32::MAX or i32::MIN still lands
+    // in the infinity or zero branch below
+    let exponent_ = exponent.saturating_add(exp).saturating_add(126);
+    if exponent_ >= 0xff {
+        return f32::from_bits(sign | 0xff << 23); // too big: infinity
+    }
+    if exponent_ > 0 {
+        return f32::from_bits(sign | (exponent_ as u32) << 23 | fraction);
+    }
+
+    // Too small for a normal float, so the result is subnormal: shift the mantissa
+    // (with its leading 1 now explicit) right until the exponent is that of the smallest
+    // normals, rounding to nearest with ties to even. Rounding up can carry into the
+    // exponent field, which correctly gives the smallest normal float.
+    let significand = fraction | 1 << 23;
+    let shift = 1 - exponent_;
+    if shift > 24 {
+        return f32::from_bits(sign); // too small even to round up to the smallest subnormal
+    }
+    let mut shifted = significand >> shift;
+    let remainder = significand & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    if remainder > half || (remainder == half && shifted & 1 == 1) {
+        shifted += 1;
+    }
+    f32::from_bits(sign | shifted)
+}
+
+pub fn frexp_ldexp_demo() {
+    assert_eq!(frexp(8.0), (0.5, 4));
+    assert_eq!(ldexp(0.5, 4), 8.0);
+
+    for x in [
+        1.0,
+        -3.75,
+        0.1,
+        f32::MAX,
+        f32::MIN_POSITIVE,
+        f32::from_bits(1),
+    ] {
+        let (mantissa, exp) = frexp(x);
+        println!("{:e} = {} x 2^{}", x, mantissa, exp);
+        assert_eq!(ldexp(mantissa, exp), x);
+    }
+}
+
+// Reinterpreted as integers, positive floats already sort in the same order as their
+// values: a bigger exponent field (or, for the same exponent, a bigger fraction) means a
+// bigger number. Negative floats sort backwards, since only the sign bit differs from
+// their absolute value. Flipping them around between i32::MIN and 0 gives an integer
+// for every float that counts through them in order, with -0.0 and 0.0 both at 0, so
+// that adjacent floats are adjacent integers.
+pub fn ordered_bits(x: f32) -> i32 {
+    let bits = x.to_bits() as i32;
+    if bits < 0 {
+        i32::MIN - bits
+    } else {
+        bits
+    }
+}
+
+fn from_ordered_bits(ordered: i32) -> f32 {
+    let bits = if ordered < 0 {
+        i32::MIN - ordered
+    } else {
+        ordered
+    };
+    f32::from_bits(bits as u32)
+}
+
+// how many representable floats apart `a` and `b` are, measured in ULPs (units in the
+// last place)
+pub fn ulp_distance(a: f32, b: f32) -> u32 {
+    ordered_bits(a).abs_diff(ordered_bits(b))
+}
+
+// the float next to `x` in the direction of `toward`
+pub fn next_after(x: f32, toward: f32) -> f32 {
+    if x.is_nan() || toward.is_nan() {
+        return f32::NAN;
+    }
+    if x == toward {
+        return toward;
+    }
+
+    let step = if toward > x { 1 } else { -1 };
+    from_ordered_bits(ordered_bits(x) + step)
+}
+
+pub fn next_after_demo() {
+    assert_eq!(next_after(1.0, 2.0), 1.0 + f32::EPSILON);
+    assert_eq!(next_after(0.0, 1.0), f32::from_bits(1)); // the smallest subnormal
+    assert_eq!(next_after(-0.0, 1.0), f32::from_bits(1));
+    assert_eq!(ulp_distance(-f32::from_bits(1), f32::from_bits(1)), 2);
+
+    // the gap between floats doubles with each power of two
+    for x in [1.0_f32, 2.0, 1024.0, 16777216.0] {
+        println!("next after {} is {}", x, next_after(x, f32::INFINITY));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_run() {
+        f80_deconstruction();
+        exact_decimal_demo();
+        frexp_ldexp_demo();
+        next_after_demo();
+        int_overflow();
+        floating_point_deconstruction(42.42);
+        cast_demo();
+        arithmetic_report_demo();
+        byte_reader_demo();
+        binary_steps_demo();
+        associativity_demo();
+        kahan_demo();
+        cancellation_examples();
+        signed_unsigned_table_demo();
+        signed_overflow_demo();
+        signed_zero_demo();
+        int_vs_int();
+        f32_as_u32();
+        twos_complement();
+        endianness();
+        floating_point_partial_eq();
+    }
+
+    #[test]
+    fn decompose_f80_one_and_two() {
+        let one = decompose_f80([0, 0, 0, 0, 0, 0, 0, 0x80, 0xff, 0x3f]);
+        assert_eq!(
+            one,
+            ExtendedFloatParts {
+                sign: 0,
+                exponent: 0x3fff,
+                integer_bit: true,
+                fraction: 0,
+            }
+        );
+        let minus_two = decompose_f80([0, 0, 0, 0, 0, 0, 0, 0x80, 0x00, 0xc0]);
+        assert_eq!((minus_two.sign, minus_two.unbiased_exponent()), (1, 1));
+        assert!(minus_two.integer_bit);
+    }
+
+    #[test]
+    fn decompose_f80_unnormal() {
+        // an exponent with no integer bit, which f32 and f64 can't express
+        let parts = decompose_f80([0, 0, 0, 0, 0, 0, 0, 0x40, 0xff, 0xbf]);
+        assert_eq!(parts.sign, 1);
+        assert!(!parts.integer_bit);
+        assert_eq!(parts.fraction, 1 << 62);
+        assert_eq!(parts.unbiased_exponent(), 0);
+    }
+
+    #[test]
+    fn exact_decimal_edge_cases() {
+        assert_eq!(exact_decimal(0.0), "0");
+        assert_eq!(exact_decimal(-0.0), "-0");
+        assert_eq!(exact_decimal(16777217.0), "16777216");
+        assert_eq!(exact_decimal(-2.5), "-2.5");
+        assert_eq!(exact_decimal(f32::NEG_INFINITY), "-inf");
+        assert_eq!(exact_decimal(f32::NAN), "NaN");
+        // the smallest subnormal, 2^-149, has 149 decimal places
+        let smallest = exact_decimal(f32::from_bits(1));
+        assert!(smallest.starts_with("0.000000000000000000000000000000000000000000001401"));
+        assert_eq!(smallest.len() - 2, 149);
+    }
+
+    #[test]
+    fn exact_decimal_shows_what_0_1_really_is() {
+        assert_eq!(exact_decimal(0.5), "0.5");
+        assert_eq!(exact_decimal(0.1), "0.100000001490116119384765625");
+    }
+
+    #[test]
+    fn frexp_and_ldexp_invert_each_other() {
+        assert_eq!(frexp(8.0), (0.5, 4));
+        assert_eq!(ldexp(0.5, 4), 8.0);
+        for x in [1.0_f32, -3.75, 0.1, 123456.79, 1e-42, f32::MAX] {
+            let (mantissa, exp) = frexp(x);
+            assert_eq!(ldexp(mantissa, exp), x, "{}", x);
+        }
+    }
+
+    #[test]
+    fn frexp_mantissa_range() {
+        for x in [1.0_f32, -1.0, 3.0, 1e-40, -f32::MAX, 0.75] {
+            let (mantissa, exp) = frexp(x);
+            assert!((0.5..1.0).contains(&mantissa.abs()), "{}", x);
+            assert_eq!(mantissa as f64 * 2f64.powi(exp), x as f64);
+        }
+        assert_eq!(frexp(-0.0), (-0.0, 0));
+        assert_eq!(frexp(f32::INFINITY), (f32::INFINITY, 0));
+        assert!(frexp(f32::NAN).0.is_nan());
+    }
+
+    #[test]
+    fn ldexp_rounds_into_the_subnormals() {
+        assert_eq!(ldexp(1.0, 128), f32::INFINITY);
+        assert_eq!(ldexp(-1.0, 128), f32::NEG_INFINITY);
+        assert_eq!(ldexp(1.0, -149), f32::from_bits(1));
+        // exactly half of the smallest subnormal ties to even, which is 0
+        assert_eq!(ldexp(1.0, -150), 0.0);
+        assert_eq!(ldexp(0.75, -149), f32::from_bits(1));
+        assert_eq!(ldexp(-0.0, 10).to_bits(), (-0.0f32).to_bits());
+    }
+
+    #[test]
+    fn next_after_steps_one_float() {
+        assert_eq!(next_after(1.0, 2.0), 1.0 + f32::EPSILON);
+        assert_eq!(next_after(0.0, 1.0), f32::from_bits(1));
+        assert_eq!(next_after(-0.0, 1.0), f32::from_bits(1));
+    }
+
+    #[test]
+    fn ordered_bits_and_next_after() {
+        assert_eq!(ordered_bits(0.0), ordered_bits(-0.0));
+        assert!(ordered_bits(-1.0) < ordered_bits(-0.5));
+        assert!(ordered_bits(f32::MAX) < ordered_bits(f32::INFINITY));
+        assert_eq!(ulp_distance(1.0, next_after(1.0, 0.0)), 1);
+        assert_eq!(next_after(f32::MAX, f32::INFINITY), f32::INFINITY);
+        assert_eq!(next_after(1.0, 1.0), 1.0);
+        assert!(next_after(f32::NAN, 1.0).is_nan());
+        assert_eq!(next_after(f32::from_bits(1), -1.0), 0.0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflow")]
+    fn plain_add_panics_on_overflow_in_debug() {
+        overflow_panics_in_debug();
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn plain_add_wraps_in_release() {
+        assert_eq!(overflow_panics_in_debug(), 144);
+    }
+
+    #[test]
+    fn explicit_wrapping_is_the_same_in_every_build() {
+        assert_eq!(200u8.wrapping_add(200), 144);
+        assert_eq!(1u16.wrapping_add(u16::MAX), 0);
+        assert_eq!(i8::MIN.wrapping_sub(1), i8::MAX);
+        assert_eq!(200u8.checked_add(56), None);
+        assert_eq!(200u8.checked_add(55), Some(255));
+    }
+
+    #[test]
+    fn labeled_bits_ruler_lines_up() {
+        let text = labeled_bits_u32(0x4229_AE14);
+        assert_eq!(
+            text,
+            "31   27   23   19   15   11   7    3\n\
+             0100 0010 0010 1001 1010 1110 0001 0100"
+        );
+
+        // each ruler label sits over the first bit of its group
+        let (ruler, bits) = text.split_once('\n').unwrap();
+        for (i, label) in ruler.match_indices(char::is_numeric) {
+            if i == 0 || !ruler.as_bytes()[i - 1].is_ascii_digit() {
+                let bit: usize = ruler[i..].split(' ').next().unwrap().parse().unwrap();
+                let position = bits[..i].chars().filter(|c| *c != ' ').count();
+                assert_eq!(31 - position, bit, "label {}", label);
+            }
+        }
+
+        for x in [0, 1, 0x8000_0000, 0xDEAD_BEEF, u32::MAX] {
+            let bits = labeled_bits_u32(x).lines().nth(1).unwrap().replace(' ', "");
+            assert_eq!(u32::from_str_radix(&bits, 2), Ok(x));
+        }
+    }
+
+    #[test]
+    fn visualize_f32_layout_splits_the_fields() {
+        let text = visualize_f32_layout(42.42);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "0|10000100|01010011010111000010100");
+        assert_eq!(lines[1], "S|EEEEEEEE|MMMMMMMMMMMMMMMMMMMMMMM");
+        // the field boundaries line up between the bits and the key
+        assert_eq!(lines[0].find('|'), Some(1));
+        assert_eq!(lines[0].rfind('|'), lines[1].rfind('|'));
+        for field in ["sign", "exponent", "mantissa"] {
+            assert!(text.contains(field), "{}", field);
+        }
+        assert!(text.contains("exponent = 132 - 127 = 5"));
+        assert!(text.contains("mantissa = 1.01010011010111000010100 (binary)"));
+    }
+
+    #[test]
+    fn visualize_f32_layout_special_exponents() {
+        assert!(visualize_f32_layout(f32::from_bits(1)).contains("(zero or subnormal)"));
+        assert!(visualize_f32_layout(f32::NEG_INFINITY).contains("sign     = 1 (-)"));
+        assert!(visualize_f32_layout(f32::NAN).contains("(infinity or NaN)"));
+    }
+
+    #[test]
+    fn cast_results() {
+        let results = cast_demo();
+        assert_eq!(results.u32_300_as_u8, 44);
+        assert_eq!(results.u32_256_as_u8, 0);
+        assert_eq!(results.i32_minus_1_as_u32, u32::MAX);
+        assert_eq!(results.i8_minus_1_as_i32, -1);
+        assert_eq!(results.i8_minus_1_as_u32, u32::MAX);
+        assert_eq!(results.u8_255_as_i32, 255);
+        // floats truncate toward zero, then saturate
+        assert_eq!(results.f32_3_9_as_i32, 3);
+        assert_eq!(results.f32_minus_3_9_as_i32, -3);
+        assert_eq!(results.f32_300_as_u8, u8::MAX);
+        assert_eq!(results.f32_minus_1_as_u8, 0);
+        assert_eq!(results.f32_nan_as_i32, 0);
+        assert_eq!(results.f32_1e10_as_i32, i32::MAX);
+    }
+
+    #[test]
+    fn arithmetic_report_for_u8() {
+        let report = arithmetic_report(200u8, 100);
+        assert_eq!(
+            report.add,
+            OpReport {
+                wrapping: 44,
+                checked: None,
+                saturating: 255,
+                overflowing: (44, true),
+            }
+        );
+        assert_eq!(arithmetic_report(100u8, 200).sub.overflowing, (156, true));
+        assert_eq!(report.mul.overflowing, (32, true));
+        assert_eq!(report.mul.saturating, 255);
+    }
+
+    #[test]
+    fn arithmetic_report_for_signed_types() {
+        let report = arithmetic_report(i8::MIN, -1);
+        assert_eq!(report.add.checked, None);
+        assert_eq!(report.add.saturating, i8::MIN);
+        assert_eq!(report.sub.checked, Some(-127));
+        assert_eq!(report.mul.overflowing, (i8::MIN, true));
+        assert_eq!(report.mul.saturating, i8::MAX);
+    }
+
+    #[test]
+    fn byte_reader_mixes_byte_orders() {
+        let buffer = [0x01, 0x02, 0x01, 0x02, 0x00, 0x00, 0x80, 0x3f, 0xff];
+        let mut reader = ByteReader::new(&buffer);
+        assert_eq!(reader.read_u16(ByteOrder::Big), Ok(0x0102));
+        assert_eq!(reader.read_u16(ByteOrder::Little), Ok(0x0201));
+        assert_eq!(reader.read_f32(ByteOrder::Little), Ok(1.0));
+        assert_eq!((reader.position(), reader.remaining()), (8, 1));
+        assert_eq!(reader.read_u8(), Ok(0xff));
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn byte_reader_failed_read_consumes_nothing() {
+        let mut reader = ByteReader::new(&[0x12, 0x34, 0x56]);
+        assert_eq!(reader.read_u16(ByteOrder::Little), Ok(0x3412));
+        assert_eq!(
+            reader.read_u32(ByteOrder::Big),
+            Err(OutOfBytes {
+                position: 2,
+                wanted: 4
+            })
+        );
+        assert_eq!(reader.remaining(), 1);
+        assert_eq!(reader.read_u8(), Ok(0x56));
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn binary_steps_for_13() {
+        let (binary, steps) = to_binary_steps(13);
+        assert_eq!(binary, "1101");
+        assert_eq!(steps, vec![(6, 1), (3, 0), (1, 1), (0, 1)]);
+        assert_eq!(from_binary_steps(&binary).map(|(n, _)| n), Some(13));
+    }
+
+    #[test]
+    fn binary_steps_roundtrip() {
+        for n in [0, 1, 2, 255, 1 << 31, u32::MAX] {
+            let (binary, _) = to_binary_steps(n);
+            assert_eq!(binary, format!("{:b}", n));
+            assert_eq!(from_binary_steps(&binary).unwrap().0, n);
+        }
+        assert_eq!(from_binary_steps(""), None);
+    }
+
+    #[test]
+    fn float_addition_is_not_associative() {
+        let (left, right, equal) = associativity_demo();
+        assert_ne!(left, right);
+        assert!(!equal);
+    }
+
+    #[test]
+    fn kahan_sum_is_closer_than_naive() {
+        // a large value followed by many that are each too small to move it
+        let mut values = vec![1e4_f32];
+        values.extend(std::iter::repeat_n(1e-4_f32, 100_000));
+        let exact = 1e4 + 10.0;
+
+        let naive = naive_sum(&values) as f64;
+        let kahan = kahan_sum(&values) as f64;
+        assert!((kahan - exact).abs() < (naive - exact).abs());
+        assert!((kahan - exact).abs() < 0.01);
+        assert_eq!(kahan_sum(&[]), 0.0);
+    }
+
+    #[test]
+    fn cancellation_loses_precision_for_tiny_x() {
+        let x = 1e-5_f64;
+        // the next term of the series, x^4 / 24, matters at this size
+        let expected = x * x / 2.0 - x.powi(4) / 24.0;
+        let (naive, stable) = cancellation_demo(x);
+        let naive_error = ((naive - expected) / expected).abs();
+        let stable_error = ((stable - expected) / expected).abs();
+        assert!(naive_error > 1e-8);
+        assert!(stable_error < 1e-12);
+    }
+
+    #[test]
+    fn signed_unsigned_table_at_8_bits() {
+        let table = signed_unsigned_table(8);
+        assert!(table.contains(&(128, -128)));
+        assert!(table.contains(&(255, -1)));
+        assert_eq!(128u8 as i8, -128);
+        assert_eq!(255u8 as i8, -1);
+    }
+
+    #[test]
+    fn signed_unsigned_table_at_other_widths() {
+        assert_eq!(
+            signed_unsigned_table(16),
+            vec![(0, 0), (1, 1), (32767, 32767), (32768, -32768), (65535, -1)]
+        );
+        assert_eq!(signed_unsigned_table(64).last(), Some(&(u64::MAX, -1)));
+        assert_eq!(signed_unsigned_table(1), vec![(0, 0), (1, -1)]);
+    }
+
+    #[test]
+    fn signed_overflow_is_detected() {
+        assert!(add_would_overflow_signed(i32::MAX, 1));
+        assert!(!add_would_overflow_signed(5, 3));
+        assert!(add_would_overflow_signed(-1, i32::MIN));
+        assert!(add_would_overflow_signed(i32::MIN, i32::MIN));
+        assert!(!add_would_overflow_signed(i32::MIN, i32::MAX));
+        assert!(!add_would_overflow_signed(-1, 1));
+    }
+
+    #[test]
+    fn signed_zeros_compare_equal_but_divide_differently() {
+        let report = signed_zero_demo();
+        assert!(report.compare_equal);
+        assert_ne!(report.one_over_positive, report.one_over_negative);
+        assert_eq!(report.positive_bits, 0);
+        assert!((-1e-30_f32 * 1e-30).is_sign_negative());
+    }
+
+    #[test]
+    fn ldexp_saturates_at_extreme_exponents() {
+        assert_eq!(ldexp(1.0, i32::MAX), f32::INFINITY);
+        assert_eq!(ldexp(-1.0, i32::MAX), f32::NEG_INFINITY);
+        assert_eq!(ldexp(1.0, i32::MIN), 0.0);
+        assert_eq!(ldexp(-1.0, i32::MIN).to_bits(), (-0.0f32).to_bits());
+        // frexp's own exponent is added first, so these are at the edge too
+        assert_eq!(ldexp(f32::MAX, i32::MAX - 1), f32::INFINITY);
+        assert_eq!(ldexp(f32::from_bits(1), i32::MIN + 1), 0.0);
+    }
+}
@@ -1,117 +1,3627 @@
 // CHIP-8 CPU emulator
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::util::{BitSet16, RingBuffer};
+
+// A decoded CHIP-8 instruction. Opcodes are 16 bits, read as four nibbles: the first
+// nibble picks the instruction family and the rest hold its operands, which are a
+// register index (x, y), an 8-bit immediate (nn), a 4-bit immediate (n) or a 12-bit
+// address (nnn).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Halt,                         // 0000
+    ClearScreen,                  // 00E0
+    Return,                       // 00EE
+    ScrollDown(u8),               // 00CN (SUPER-CHIP)
+    ScrollRight,                  // 00FB (SUPER-CHIP)
+    ScrollLeft,                   // 00FC (SUPER-CHIP)
+    Exit,                         // 00FD (SUPER-CHIP)
+    LowRes,                       // 00FE (SUPER-CHIP)
+    HighRes,                      // 00FF (SUPER-CHIP)
+    SysCall(u16),                 // 0NNN
+    Jump(u16),                    // 1NNN
+    Call(u16),                    // 2NNN
+    SkipEqImm { x: u8, nn: u8 },  // 3XNN
+    SkipNeImm { x: u8, nn: u8 },  // 4XNN
+    SkipEqXY { x: u8, y: u8 },    // 5XY0
+    LoadImm { x: u8, nn: u8 },    // 6XNN
+    AddImm { x: u8, nn: u8 },     // 7XNN
+    LoadXY { x: u8, y: u8 },      // 8XY0
+    OrXY { x: u8, y: u8 },        // 8XY1
+    AndXY { x: u8, y: u8 },       // 8XY2
+    XorXY { x: u8, y: u8 },       // 8XY3
+    AddXY { x: u8, y: u8 },       // 8XY4
+    SubXY { x: u8, y: u8 },       // 8XY5
+    ShrXY { x: u8, y: u8 },       // 8XY6
+    SubnXY { x: u8, y: u8 },      // 8XY7
+    ShlXY { x: u8, y: u8 },       // 8XYE
+    SkipNeXY { x: u8, y: u8 },    // 9XY0
+    LoadIndex(u16),               // ANNN
+    JumpV0(u16),                  // BNNN
+    Random { x: u8, nn: u8 },     // CXNN
+    Draw { x: u8, y: u8, n: u8 }, // DXYN
+    SkipKeyPressed { x: u8 },     // EX9E
+    SkipKeyNotPressed { x: u8 },  // EXA1
+    LoadDelay { x: u8 },          // FX07
+    WaitKey { x: u8 },            // FX0A
+    SetDelay { x: u8 },           // FX15
+    SetSound { x: u8 },           // FX18
+    AddIndex { x: u8 },           // FX1E
+    LoadFont { x: u8 },           // FX29
+    SelectPlanes(u8),             // FN01 (XO-CHIP)
+    LoadLargeFont { x: u8 },      // FX30 (SUPER-CHIP)
+    StoreBcd { x: u8 },           // FX33
+    StoreRegs { x: u8 },          // FX55
+    LoadRegs { x: u8 },           // FX65
+    SaveFlags { x: u8 },          // FX75 (SUPER-CHIP)
+    LoadFlags { x: u8 },          // FX85 (SUPER-CHIP)
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let c = ((opcode & 0xF000) >> 12) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let d = (opcode & 0x000F) as u8;
+
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+
+    match (c, x, y, d) {
+        (0, 0, 0, 0) => Instruction::Halt,
+        (0, 0, 0xE, 0) => Instruction::ClearScreen,
+        (0, 0, 0xE, 0xE) => Instruction::Return,
+        (0, 0, 0xC, _) => Instruction::ScrollDown(d),
+        (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+        (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+        (0, 0, 0xF, 0xD) => Instruction::Exit,
+        (0, 0, 0xF, 0xE) => Instruction::LowRes,
+        (0, 0, 0xF, 0xF) => Instruction::HighRes,
+        (0, _, _, _) => Instruction::SysCall(nnn),
+        (0x1, _, _, _) => Instruction::Jump(nnn),
+        (0x2, _, _, _) => Instruction::Call(nnn),
+        (0x3, _, _, _) => Instruction::SkipEqImm { x, nn },
+        (0x4, _, _, _) => Instruction::SkipNeImm { x, nn },
+        (0x5, _, _, 0) => Instruction::SkipEqXY { x, y },
+        (0x6, _, _, _) => Instruction::LoadImm { x, nn },
+        (0x7, _, _, _) => Instruction::AddImm { x, nn },
+        (0x8, _, _, 0x0) => Instruction::LoadXY { x, y },
+        (0x8, _, _, 0x1) => Instruction::OrXY { x, y },
+        (0x8, _, _, 0x2) => Instruction::AndXY { x, y },
+        (0x8, _, _, 0x3) => Instruction::XorXY { x, y },
+        (0x8, _, _, 0x4) => Instruction::AddXY { x, y },
+        (0x8, _, _, 0x5) => Instruction::SubXY { x, y },
+        (0x8, _, _, 0x6) => Instruction::ShrXY { x, y },
+        (0x8, _, _, 0x7) => Instruction::SubnXY { x, y },
+        (0x8, _, _, 0xE) => Instruction::ShlXY { x, y },
+        (0x9, _, _, 0) => Instruction::SkipNeXY { x, y },
+        (0xA, _, _, _) => Instruction::LoadIndex(nnn),
+        (0xB, _, _, _) => Instruction::JumpV0(nnn),
+        (0xC, _, _, _) => Instruction::Random { x, nn },
+        (0xD, _, _, _) => Instruction::Draw { x, y, n: d },
+        (0xE, _, 0x9, 0xE) => Instruction::SkipKeyPressed { x },
+        (0xE, _, 0xA, 0x1) => Instruction::SkipKeyNotPressed { x },
+        (0xF, _, 0x0, 0x7) => Instruction::LoadDelay { x },
+        (0xF, _, 0x0, 0xA) => Instruction::WaitKey { x },
+        (0xF, _, 0x1, 0x5) => Instruction::SetDelay { x },
+        (0xF, _, 0x1, 0x8) => Instruction::SetSound { x },
+        (0xF, _, 0x1, 0xE) => Instruction::AddIndex { x },
+        (0xF, _, 0x0, 0x1) => Instruction::SelectPlanes(x),
+        (0xF, _, 0x2, 0x9) => Instruction::LoadFont { x },
+        (0xF, _, 0x3, 0x0) => Instruction::LoadLargeFont { x },
+        (0xF, _, 0x3, 0x3) => Instruction::StoreBcd { x },
+        (0xF, _, 0x5, 0x5) => Instruction::StoreRegs { x },
+        (0xF, _, 0x6, 0x5) => Instruction::LoadRegs { x },
+        (0xF, _, 0x7, 0x5) => Instruction::SaveFlags { x },
+        (0xF, _, 0x8, 0x5) => Instruction::LoadFlags { x },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+// the inverse of decode, so decode(encode(i)) == i for any i that decode produces.
+// Operands are masked to the width of their field.
+pub fn encode(instruction: Instruction) -> u16 {
+    let xnn = |c: u16, x: u8, nn: u8| c << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+    let xyn = |c: u16, x: u8, y: u8, n: u8| xnn(c, x, (y & 0xF) << 4 | (n & 0xF));
+    let nnn = |c: u16, nnn: u16| c << 12 | (nnn & 0x0FFF);
+
+    match instruction {
+        Instruction::Halt => 0x0000,
+        Instruction::ClearScreen => 0x00E0,
+        Instruction::Return => 0x00EE,
+        Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::LowRes => 0x00FE,
+        Instruction::HighRes => 0x00FF,
+        Instruction::SysCall(addr) => nnn(0x0, addr),
+        Instruction::Jump(addr) => nnn(0x1, addr),
+        Instruction::Call(addr) => nnn(0x2, addr),
+        Instruction::SkipEqImm { x, nn } => xnn(0x3, x, nn),
+        Instruction::SkipNeImm { x, nn } => xnn(0x4, x, nn),
+        Instruction::SkipEqXY { x, y } => xyn(0x5, x, y, 0x0),
+        Instruction::LoadImm { x, nn } => xnn(0x6, x, nn),
+        Instruction::AddImm { x, nn } => xnn(0x7, x, nn),
+        Instruction::LoadXY { x, y } => xyn(0x8, x, y, 0x0),
+        Instruction::OrXY { x, y } => xyn(0x8, x, y, 0x1),
+        Instruction::AndXY { x, y } => xyn(0x8, x, y, 0x2),
+        Instruction::XorXY { x, y } => xyn(0x8, x, y, 0x3),
+        Instruction::AddXY { x, y } => xyn(0x8, x, y, 0x4),
+        Instruction::SubXY { x, y } => xyn(0x8, x, y, 0x5),
+        Instruction::ShrXY { x, y } => xyn(0x8, x, y, 0x6),
+        Instruction::SubnXY { x, y } => xyn(0x8, x, y, 0x7),
+        Instruction::ShlXY { x, y } => xyn(0x8, x, y, 0xE),
+        Instruction::SkipNeXY { x, y } => xyn(0x9, x, y, 0x0),
+        Instruction::LoadIndex(addr) => nnn(0xA, addr),
+        Instruction::JumpV0(addr) => nnn(0xB, addr),
+        Instruction::Random { x, nn } => xnn(0xC, x, nn),
+        Instruction::Draw { x, y, n } => xyn(0xD, x, y, n),
+        Instruction::SkipKeyPressed { x } => xnn(0xE, x, 0x9E),
+        Instruction::SkipKeyNotPressed { x } => xnn(0xE, x, 0xA1),
+        Instruction::LoadDelay { x } => xnn(0xF, x, 0x07),
+        Instruction::WaitKey { x } => xnn(0xF, x, 0x0A),
+        Instruction::SetDelay { x } => xnn(0xF, x, 0x15),
+        Instruction::SetSound { x } => xnn(0xF, x, 0x18),
+        Instruction::AddIndex { x } => xnn(0xF, x, 0x1E),
+        Instruction::LoadFont { x } => xnn(0xF, x, 0x29),
+        Instruction::SelectPlanes(n) => xnn(0xF, n, 0x01),
+        Instruction::LoadLargeFont { x } => xnn(0xF, x, 0x30),
+        Instruction::StoreBcd { x } => xnn(0xF, x, 0x33),
+        Instruction::StoreRegs { x } => xnn(0xF, x, 0x55),
+        Instruction::LoadRegs { x } => xnn(0xF, x, 0x65),
+        Instruction::SaveFlags { x } => xnn(0xF, x, 0x75),
+        Instruction::LoadFlags { x } => xnn(0xF, x, 0x85),
+        Instruction::Unknown(opcode) => opcode,
+    }
+}
+
+// Which CHIP-8 variant the CPU emulates. SUPER-CHIP extends the original with a
+// 128x64 high resolution mode, screen scrolling and 16x16 sprites; in plain CHIP-8
+// mode those opcodes fall back to being 0NNN machine code calls. XO-CHIP extends
+// SUPER-CHIP with a second bit-plane, so each pixel can show one of four colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+pub const PLANES: usize = 2;
+
+// save states start with SAVE_MAGIC and SAVE_VERSION; bump the version whenever the
+// layout written by CPU::serialize changes
+const SAVE_MAGIC: &[u8; 4] = b"CH8S";
+const SAVE_VERSION: u8 = 2;
+
+// where programs are loaded, above the memory the original interpreter occupied
+pub const PROGRAM_START: usize = 0x200;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// the standard 4x5 hex digit glyphs for FX29, five bytes per digit from 0 to F. They go
+// at FONT_ADDRESS unless moved with CPU::set_font_address.
+pub const FONT_ADDRESS: usize = 0x050;
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// SUPER-CHIP's 8x10 digit glyphs for FX30, ten bytes per digit from 0 to 9
+pub const LARGE_FONT_ADDRESS: usize = 0x0A0;
+pub const LARGE_FONT: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+// Where things live in the 4KiB address space by default. On the original machines
+// the interpreter itself took up everything below PROGRAM_START; here that space just
+// holds the fonts.
+pub fn memory_map() -> Vec<(Range<usize>, &'static str)> {
+    vec![
+        (0x000..FONT_ADDRESS, "reserved for the interpreter"),
+        (FONT_ADDRESS..FONT_ADDRESS + FONT.len(), "font"),
+        (
+            LARGE_FONT_ADDRESS..LARGE_FONT_ADDRESS + LARGE_FONT.len(),
+            "large font",
+        ),
+        (
+            LARGE_FONT_ADDRESS + LARGE_FONT.len()..PROGRAM_START,
+            "reserved for the interpreter",
+        ),
+        (PROGRAM_START..0x1000, "program and work RAM"),
+    ]
+}
+
+// Why `run` stopped, or for `step`, whether the CPU can carry on. A CPU waiting for a
+// key has rewound its PC to the FX0A, so running it again once a key is down resumes
+// from the same instruction. Breakpoints stop `run` before the instruction at the
+// breakpoint, so `step` past it before running on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    Running,
+    Halted,
+    WaitingForKey(u8),
+    Breakpoint(usize),
+    // a watched location was changed by the instruction just executed
+    Watchpoint { location: Watched, old: u8, new: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watched {
+    Register(u8),
+    Memory(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipError {
+    StackOverflow,
+    StackUnderflow,
+    UnknownOpcode { opcode: u16, addr: usize },
+    UnsupportedSysCall(u16),
+    StepLimitExceeded,
+    PcOutOfBounds(usize),
+    AddressOutOfBounds(usize),
+    RomTooLarge(usize),
+    InvalidSaveState(&'static str),
+    NothingToRewind,
+}
+
+impl fmt::Display for ChipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChipError::StackOverflow => write!(f, "stack overflow"),
+            ChipError::StackUnderflow => write!(f, "stack underflow"),
+            ChipError::UnknownOpcode { opcode, addr } => write!(
+                f,
+                "unknown opcode {:#06X} at address {:#05X} (high nibble {:X})",
+                opcode,
+                addr,
+                opcode >> 12
+            ),
+            ChipError::UnsupportedSysCall(nnn) => {
+                write!(f, "unsupported machine code routine at {:#05X}", nnn)
+            }
+            ChipError::StepLimitExceeded => write!(f, "step limit exceeded without halting"),
+            ChipError::PcOutOfBounds(pc) => write!(f, "program counter {:#X} out of bounds", pc),
+            ChipError::AddressOutOfBounds(addr) => write!(f, "address {:#X} out of bounds", addr),
+            ChipError::RomTooLarge(len) => write!(f, "ROM of {} bytes doesn't fit in memory", len),
+            ChipError::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            ChipError::NothingToRewind => write!(f, "no earlier state to step back to"),
+        }
+    }
+}
+
+impl std::error::Error for ChipError {}
+
+// Where CXNN gets its random bytes from. Swapping in a seeded source makes runs of
+// ROMs that use CXNN reproducible.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+}
+
+// xorshift64 generator: cheap, dependency-free, and more than random enough for games
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        // run the seed through a splitmix64 round so that similar seeds give unrelated
+        // streams, and so the state is never 0 (xorshift would get stuck there)
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        SeededRng {
+            state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn next_byte(&mut self) -> u8 {
+        // the high bits of xorshift output are better mixed than the low ones
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+// a - b, wrapping, and the flag 8XY5 and 8XY7 put in VF: 1 if there was NO borrow
+// (a >= b) and 0 if there was, the reverse of what a borrow flag usually means
+fn sub_with_flag(a: u8, b: u8) -> (u8, u8) {
+    (a.wrapping_sub(b), if a >= b { 1 } else { 0 })
+}
+
+// a shift by one bit and the flag 8XY6 and 8XYE put in VF: the bit that was shifted out
+fn shr_with_flag(v: u8) -> (u8, u8) {
+    (v >> 1, v & 1)
+}
+
+fn shl_with_flag(v: u8) -> (u8, u8) {
+    (v << 1, (v >> 7) & 1)
+}
+
+fn is_super_chip_only(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadLargeFont { .. }
+            | Instruction::SaveFlags { .. }
+            | Instruction::LoadFlags { .. }
+    )
+}
+
+fn is_xo_chip_only(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::SelectPlanes(_))
+}
+
+// Whether `opcode` is part of the instruction set of `mode`, for tools that want to
+// flag a ROM using instructions from a later variant. Each variant is a superset of the
+// one before. 0NNN machine code calls are part of every variant, even though they
+// can't be emulated.
+pub fn is_legal_opcode(opcode: u16, mode: Mode) -> bool {
+    let instruction = decode(opcode);
+    if let Instruction::Unknown(_) = instruction {
+        return false;
+    }
+
+    match mode {
+        Mode::Chip8 => !is_super_chip_only(instruction) && !is_xo_chip_only(instruction),
+        Mode::SuperChip => !is_xo_chip_only(instruction),
+        Mode::XoChip => true,
+    }
+}
+
+// A device mapped into part of the address space. Loads and stores by instructions
+// (not opcode fetches) that land in the mapped range are passed to it first: on_read
+// can answer in place of memory, or return None to let the load fall through, and
+// on_write sees each store before it reaches memory as usual.
+pub trait Peripheral {
+    fn on_write(&mut self, addr: usize, value: u8);
+    fn on_read(&mut self, addr: usize) -> Option<u8>;
+}
+
+// A copy of everything that makes up the CPU's architectural state, taken with
+// CPU::state. Unlike the CPU itself it holds no callbacks, so it can be cloned and
+// compared freely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub registers: [u8; 16],
+    pub memory: [u8; 0x1000],
+    pub pc: usize,
+    pub index: u16,
+    pub stack: [u16; 16],
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [bool; 16],
+    pub display: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    pub planes: u8,
+    pub hires: bool,
+    pub rpl_flags: [u8; 8],
+    pub instruction_count: u64,
+}
+
+// The furthest the PC can get from the end of memory: BNNN can jump to 0xFFF + 0xFF.
+// Under PcOverflowPolicy::Error the next fetch from past the end fails, but the PC
+// still gets there, and so does a skip over the last instruction (to 0x1002).
+pub const MAX_PC: usize = 0xFFF + 0xFF;
+
+impl CpuState {
+    // properties that should hold after any sequence of instructions, whatever they were:
+    // the stack pointer stays within the stack, the PC is no further than MAX_PC, and
+    // the return addresses stay within memory. A call from the last instruction pushes
+    // the address just past the end, which still counts.
+    pub fn invariants_hold(&self) -> bool {
+        let in_memory = |addr: usize| addr <= self.memory.len();
+
+        self.stack_pointer <= self.stack.len()
+            && self.pc <= MAX_PC
+            && self.stack[..self.stack_pointer]
+                .iter()
+                .all(|&addr| in_memory(addr as usize))
+    }
+}
+
+// The sixteen general purpose registers, for naming them without bare indices
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    V0,
+    V1,
+    V2,
+    V3,
+    V4,
+    V5,
+    V6,
+    V7,
+    V8,
+    V9,
+    VA,
+    VB,
+    VC,
+    VD,
+    VE,
+    VF,
+}
+
+// VF doubles as the flag register: carries, borrows, shifted-out bits and sprite
+// collisions all land in it, overwriting whatever the program had there
+pub const FLAG: Reg = Reg::VF;
+
+// What happens when the PC runs off the end of memory: either it wraps around to 0
+// (the two bytes of an opcode at the last address are split across the end and the
+// start), or the fetch fails with ChipError::PcOutOfBounds, which is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PcOverflowPolicy {
+    WrapAround,
+    #[default]
+    Error,
+}
+
+// Behaviours that differ between interpreters, which some ROMs depend on one way or
+// the other. Everything is off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    // FX1E sets VF to 1 when I goes past 0xFFF and to 0 otherwise, as the Amiga
+    // interpreter did (Spacefight 2091! relies on it); normally VF is left alone
+    pub fx1e_sets_vf: bool,
+    // DXYN waits for the vertical blank, as on the COSMAC VIP, so run_frame draws at
+    // most one sprite per frame and leaves any further draw for the next one
+    pub display_wait: bool,
+    // 8XY6 and 8XYE shift Vy and put the result in Vx, as on the COSMAC VIP; otherwise,
+    // as from SUPER-CHIP on, they shift Vx in place and ignore Vy
+    pub shift_uses_vy: bool,
+}
+
+type FrameCallback = Box<dyn FnMut(&CPU)>;
+
+// What changed between two CpuStates, as (old, new) pairs. Registers are keyed by
+// index and memory by address, in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<(u8, u8, u8)>,
+    pub memory: Vec<(usize, u8, u8)>,
+    pub pc: Option<(usize, usize)>,
+    pub index: Option<(u16, u16)>,
+    pub stack_pointer: Option<(usize, usize)>,
+    pub delay_timer: Option<(u8, u8)>,
+    pub sound_timer: Option<(u8, u8)>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+}
+
+impl CpuState {
+    // what changed going from `self` to `other`
+    pub fn diff(&self, other: &CpuState) -> StateDiff {
+        fn changed<T: PartialEq>(old: T, new: T) -> Option<(T, T)> {
+            (old != new).then_some((old, new))
+        }
+
+        StateDiff {
+            registers: diff_memory(&self.registers, &other.registers)
+                .into_iter()
+                .map(|(i, old, new)| (i as u8, old, new))
+                .collect(),
+            memory: diff_memory(&self.memory, &other.memory),
+            pc: changed(self.pc, other.pc),
+            index: changed(self.index, other.index),
+            stack_pointer: changed(self.stack_pointer, other.stack_pointer),
+            delay_timer: changed(self.delay_timer, other.delay_timer),
+            sound_timer: changed(self.sound_timer, other.sound_timer),
+        }
+    }
+}
+
+// (address, old, new) for every byte that differs. Only the common prefix of the two
+// slices is compared.
+pub fn diff_memory(before: &[u8], after: &[u8]) -> Vec<(usize, u8, u8)> {
+    before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(addr, (&old, &new))| (addr, old, new))
+        .collect()
+}
+
 pub struct CPU {
     pub registers: [u8; 16],
     pub memory: [u8; 0x1000],
     position_in_memory: usize,
     stack: [u16; 16],
     stack_pointer: usize,
+    instruction_count: u64,
+    // every distinct opcode fetched since power-on or the last reset
+    coverage: HashSet<u16>,
+    // executions per opcode high nibble, only counted once profiling is enabled
+    profiling: bool,
+    profile: HashMap<u8, u64>,
+    breakpoints: HashSet<usize>,
+    watchpoints: Vec<Watched>,
+    // snapshots from before the most recent steps, as many as rewinding was enabled for
+    history: RingBuffer<CpuState>,
+    keys: BitSet16,
+    index: u16,
+    mode: Mode,
+    // rows of pixels, sized for high resolution; low resolution only uses the top-left
+    // corner. Each pixel holds one bit per plane, bit 0 being plane 0.
+    display: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    // bitmask of the planes that drawing, clearing and scrolling apply to
+    planes: u8,
+    hires: bool,
+    display_dirty: bool,
+    // per-pixel brightness for CRT-style phosphor persistence, once fading is enabled:
+    // lit pixels are at 255 and unlit ones lose fade_rate every frame
+    fade: Option<[[u8; HIRES_WIDTH]; HIRES_HEIGHT]>,
+    fade_rate: u8,
+    font_address: usize,
+    // SUPER-CHIP's "RPL user flags", named after the HP-48 calculator registers they lived in
+    rpl_flags: [u8; 8],
+    rng: Box<dyn RandomSource>,
+    delay_timer: u8,
+    sound_timer: u8,
+    beeping: bool,
+    on_beep: Option<Box<dyn FnMut(bool)>>,
+    peripheral: Option<(Range<usize>, Box<dyn Peripheral>)>,
+    on_pc_overflow: PcOverflowPolicy,
+    quirks: Quirks,
+    on_frame: Option<FrameCallback>,
 }
 
 impl CPU {
     pub fn new() -> CPU {
-        CPU {
+        CPU::with_mode(Mode::Chip8)
+    }
+
+    pub fn with_mode(mode: Mode) -> CPU {
+        let mut cpu = CPU {
             registers: [0; 16],
             memory: [0; 4096],
             position_in_memory: 0,
             stack: [0; 16],
             stack_pointer: 0,
+            instruction_count: 0,
+            coverage: HashSet::new(),
+            profiling: false,
+            profile: HashMap::new(),
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+            history: RingBuffer::new(0),
+            keys: BitSet16::new(),
+            index: 0,
+            mode,
+            display: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            planes: 0b01,
+            hires: false,
+            display_dirty: false,
+            fade: None,
+            fade_rate: 0,
+            font_address: FONT_ADDRESS,
+            rpl_flags: [0; 8],
+            rng: Box::new(SeededRng::new(time_seed())),
+            delay_timer: 0,
+            sound_timer: 0,
+            beeping: false,
+            on_beep: None,
+            peripheral: None,
+            on_pc_overflow: PcOverflowPolicy::default(),
+            quirks: Quirks::default(),
+            on_frame: None,
+        };
+
+        cpu.load_fonts();
+        cpu
+    }
+
+    // put the CPU back in its power-on state, keeping its mode, font address, PC overflow
+    // policy, quirks, breakpoints, watchpoints, fade rate and random source. The
+    // existing memory is zeroed in place rather than reallocated.
+    pub fn reset(&mut self) {
+        self.registers = [0; 16];
+        self.memory.fill(0);
+        self.position_in_memory = 0;
+        self.stack = [0; 16];
+        self.stack_pointer = 0;
+        self.instruction_count = 0;
+        self.coverage.clear();
+        self.profile.clear();
+        self.history.clear();
+        self.keys = BitSet16::new();
+        self.index = 0;
+        self.display = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.planes = 0b01;
+        self.hires = false;
+        self.display_dirty = false;
+        if let Some(fade) = &mut self.fade {
+            *fade = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
         }
+        self.rpl_flags = [0; 8];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.set_beeping(false);
+        self.load_fonts();
+    }
+
+    fn load_fonts(&mut self) {
+        // the small font goes last so that, if it has been moved on top of the large
+        // one, it wins
+        let large_font = LARGE_FONT_ADDRESS..LARGE_FONT_ADDRESS + LARGE_FONT.len();
+        self.memory[large_font].copy_from_slice(&LARGE_FONT);
+        let font = self.font_address..self.font_address + FONT.len();
+        self.memory[font].copy_from_slice(&FONT);
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<HaltReason, ChipError> {
         loop {
-            let opcode = self.read_opcode();
-            self.position_in_memory += 2;
+            match self.step_unless_breakpoint()? {
+                HaltReason::Running => continue,
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), ChipError> {
+        self.load_rom_at(rom, PROGRAM_START)
+    }
+
+    // load a ROM somewhere other than PROGRAM_START, e.g. 0x600 for the ETI-660, and
+    // start executing from there
+    pub fn load_rom_at(&mut self, rom: &[u8], addr: usize) -> Result<(), ChipError> {
+        if addr > self.memory.len() || rom.len() > self.memory.len() - addr {
+            return Err(ChipError::RomTooLarge(rom.len()));
+        }
+
+        self.memory[addr..addr + rom.len()].copy_from_slice(rom);
+        self.position_in_memory = addr;
+        Ok(())
+    }
+
+    pub fn reg(&self, reg: Reg) -> u8 {
+        self.registers[reg as usize]
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, value: u8) {
+        self.registers[reg as usize] = value;
+    }
+
+    // Vx by number, for opcode handlers and anything else with a raw register index.
+    // Only the low nibble of `x` is used, so any u8 picks a register rather than
+    // indexing past the end: 0x1F is VF.
+    pub fn get_register(&self, x: u8) -> u8 {
+        self.registers[(x & 0x0F) as usize]
+    }
+
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.registers[(x & 0x0F) as usize] = value;
+    }
+
+    // bounds-checked access to memory, for setting up and inspecting programs. These go
+    // straight to memory, bypassing any attached peripheral.
+    pub fn read_byte(&self, addr: usize) -> Result<u8, ChipError> {
+        self.memory
+            .get(addr)
+            .copied()
+            .ok_or(ChipError::AddressOutOfBounds(addr))
+    }
+
+    pub fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), ChipError> {
+        let byte = self
+            .memory
+            .get_mut(addr)
+            .ok_or(ChipError::AddressOutOfBounds(addr))?;
+        *byte = value;
+        Ok(())
+    }
+
+    // writes all of `bytes` or, if they run past the end of memory, none of them
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), ChipError> {
+        let end = addr.saturating_add(bytes.len());
+        if end > self.memory.len() {
+            return Err(ChipError::AddressOutOfBounds(addr.max(self.memory.len())));
+        }
+        self.memory[addr..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // load and run an arbitrary ROM with a fixed random seed, for fuzzing. Any input,
+    // however malformed, ends in an Ok or an Err rather than a panic.
+    pub fn from_rom_and_run(rom: &[u8], max_steps: u64) -> Result<HaltReason, ChipError> {
+        let mut cpu = CPU::new();
+        cpu.set_random_source(SeededRng::new(0));
+        cpu.load_rom(rom)?;
+        cpu.run_bounded(max_steps)
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            memory: self.memory,
+            pc: self.position_in_memory,
+            index: self.index,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keys: self.keys.to_array(),
+            display: self.display,
+            planes: self.planes,
+            hires: self.hires,
+            rpl_flags: self.rpl_flags,
+            instruction_count: self.instruction_count,
+        }
+    }
+
+    // put back a state taken with `state`. Callbacks, configuration and debugging aids
+    // like breakpoints are left as they are.
+    pub fn restore(&mut self, state: &CpuState) {
+        self.registers = state.registers;
+        self.memory = state.memory;
+        self.position_in_memory = state.pc;
+        self.index = state.index;
+        self.stack = state.stack;
+        self.stack_pointer = state.stack_pointer;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.keys = BitSet16::from(state.keys);
+        self.display = state.display;
+        self.planes = state.planes;
+        self.hires = state.hires;
+        self.rpl_flags = state.rpl_flags;
+        self.instruction_count = state.instruction_count;
+        self.display_dirty = true;
+    }
+
+    // Keep a snapshot from before each of the last `history` steps, so they can be undone
+    // with step_back. Every snapshot is a full CpuState, memory and frame buffer included,
+    // which is over 12KiB each: a history of 1000 steps costs about 12MiB. A history of 0
+    // turns rewinding off again.
+    pub fn enable_rewind(&mut self, history: usize) {
+        self.history.set_capacity(history);
+    }
+
+    // undo the most recent step
+    pub fn step_back(&mut self) -> Result<(), ChipError> {
+        let state = self.history.pop_back().ok_or(ChipError::NothingToRewind)?;
+        self.restore(&state);
+        Ok(())
+    }
+
+    // which region of memory_map `addr` is in, going by where this CPU's font actually
+    // is if it has been moved
+    pub fn describe_address(&self, addr: usize) -> &'static str {
+        if (self.font_address..self.font_address + FONT.len()).contains(&addr) {
+            return "font";
+        }
+        match memory_map()
+            .into_iter()
+            .find(|(region, _)| region.contains(&addr))
+        {
+            // where the font would be, had it not been moved somewhere else
+            Some((_, "font")) => "reserved for the interpreter",
+            Some((_, name)) => name,
+            None => "out of bounds",
+        }
+    }
+
+    // Move the FX29 font to `addr`, clearing it from where it was, or fail without
+    // changing anything if it wouldn't fit. Both fonts are then loaded again, since the
+    // clearing wipes the large font too if the small one had been moved on top of it.
+    pub fn set_font_address(&mut self, addr: usize) -> Result<(), ChipError> {
+        if addr > self.memory.len() - FONT.len() {
+            return Err(ChipError::AddressOutOfBounds(addr));
+        }
 
-            let c = ((opcode & 0xF000) >> 12) as u8;
-            let x = ((opcode & 0x0F00) >> 8) as u8;
-            let y = ((opcode & 0x00F0) >> 4) as u8;
-            let d = ((opcode & 0x000F) >> 0) as u8;
+        self.memory[self.font_address..self.font_address + FONT.len()].fill(0);
+        self.font_address = addr;
+        self.load_fonts();
+        Ok(())
+    }
+
+    pub fn font_address(&self) -> usize {
+        self.font_address
+    }
+
+    // map a peripheral over `region`, replacing any previously attached one
+    pub fn attach_peripheral(
+        &mut self,
+        region: Range<usize>,
+        peripheral: impl Peripheral + 'static,
+    ) {
+        self.peripheral = Some((region, Box::new(peripheral)));
+    }
+
+    pub fn detach_peripheral(&mut self) {
+        self.peripheral = None;
+    }
+
+    // Save state as bytes: SAVE_MAGIC, SAVE_VERSION, then each field as a little-endian
+    // u32 length followed by that many bytes. The random source, callbacks,
+    // peripheral, PC overflow policy, coverage and profile aren't part of the state and
+    // aren't saved.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(SAVE_MAGIC);
+        out.push(SAVE_VERSION);
+
+        let mode = match self.mode {
+            Mode::Chip8 => 0,
+            Mode::SuperChip => 1,
+            Mode::XoChip => 2,
+        };
+        let stack: Vec<u8> = self
+            .stack
+            .iter()
+            .flat_map(|addr| addr.to_le_bytes())
+            .collect();
+        let keys = self.keys.to_array().map(|pressed| pressed as u8);
+
+        put_field(&mut out, &[mode]);
+        put_field(&mut out, &self.registers);
+        put_field(&mut out, &self.memory);
+        put_field(&mut out, &(self.position_in_memory as u32).to_le_bytes());
+        put_field(&mut out, &self.index.to_le_bytes());
+        put_field(&mut out, &stack);
+        put_field(&mut out, &[self.stack_pointer as u8]);
+        put_field(&mut out, &self.instruction_count.to_le_bytes());
+        put_field(&mut out, &keys);
+        put_field(&mut out, self.display.as_flattened());
+        put_field(&mut out, &[self.planes, self.hires as u8]);
+        put_field(&mut out, &self.rpl_flags);
+        put_field(&mut out, &[self.delay_timer, self.sound_timer]);
+        put_field(&mut out, &(self.font_address as u32).to_le_bytes());
+        out
+    }
+
+    // the inverse of serialize, rejecting anything with the wrong header or whose fields
+    // don't describe a state the CPU could actually be in
+    pub fn deserialize(bytes: &[u8]) -> Result<CPU, ChipError> {
+        let invalid = ChipError::InvalidSaveState;
+        let body = bytes.strip_prefix(SAVE_MAGIC).ok_or(invalid("bad magic"))?;
+        let (&version, body) = body.split_first().ok_or(invalid("missing version"))?;
+        if version != SAVE_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let mut fields = SaveReader { bytes: body };
+
+        let [mode] = fields.next()?;
+        let mode = match mode {
+            0 => Mode::Chip8,
+            1 => Mode::SuperChip,
+            2 => Mode::XoChip,
+            _ => return Err(invalid("unknown mode")),
+        };
+        let mut cpu = CPU::with_mode(mode);
+
+        cpu.registers = fields.next()?;
+        cpu.memory = fields.next()?;
+        cpu.position_in_memory = u32::from_le_bytes(fields.next()?) as usize;
+        cpu.index = u16::from_le_bytes(fields.next()?);
+        let stack: [u8; 32] = fields.next()?;
+        for (addr, bytes) in cpu.stack.iter_mut().zip(stack.chunks_exact(2)) {
+            *addr = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        let [stack_pointer] = fields.next()?;
+        cpu.stack_pointer = stack_pointer as usize;
+        cpu.instruction_count = u64::from_le_bytes(fields.next()?);
+        let keys: [u8; 16] = fields.next()?;
+        cpu.keys = BitSet16::from(keys.map(|pressed| pressed != 0));
+        let display: [u8; HIRES_WIDTH * HIRES_HEIGHT] = fields.next()?;
+        for (row, pixels) in cpu
+            .display
+            .iter_mut()
+            .zip(display.chunks_exact(HIRES_WIDTH))
+        {
+            row.copy_from_slice(pixels);
+        }
+        let [planes, hires] = fields.next()?;
+        cpu.planes = planes;
+        cpu.hires = hires != 0;
+        cpu.rpl_flags = fields.next()?;
+        [cpu.delay_timer, cpu.sound_timer] = fields.next()?;
+        cpu.font_address = u32::from_le_bytes(fields.next()?) as usize;
 
-            let nnn = opcode & 0x0FFF;
+        if !fields.bytes.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        if cpu.font_address + FONT.len() > cpu.memory.len() {
+            return Err(invalid("font address out of range"));
+        }
+        if !cpu.state().invariants_hold() {
+            return Err(invalid("stack pointer or PC out of range"));
+        }
+        cpu.display_dirty = true;
+        Ok(cpu)
+    }
 
-            match (c, x, y, d) {
-                (0, 0, 0, 0) => break,
-                (0, 0, 0xE, 0xE) => self.ret(),
-                (0x2, _, _, _) => self.call(nnn),
-                (0x8, _, _, 0x4) => self.add_xy(x, y),
-                _ => todo!(),
+    // like run, but gives up with StepLimitExceeded after max_steps instructions,
+    // so a program stuck in a loop can't hang the caller
+    pub fn run_bounded(&mut self, max_steps: u64) -> Result<HaltReason, ChipError> {
+        for _ in 0..max_steps {
+            match self.step_unless_breakpoint()? {
+                HaltReason::Running => continue,
+                reason => return Ok(reason),
             }
         }
+
+        Err(ChipError::StepLimitExceeded)
     }
 
-    fn read_opcode(&self) -> u16 {
-        let p = self.position_in_memory;
-        let op_byte1 = self.memory[p] as u16;
-        let op_byte2 = self.memory[p + 1] as u16;
+    // Run like run does, but in real time: instructions are spaced out to
+    // `instructions_per_second` and the timers tick `timer_hz` times a second (60 on
+    // real hardware). This sleeps between instructions, blocking the calling thread
+    // until the program stops.
+    pub fn run_at_hz(
+        &mut self,
+        instructions_per_second: u32,
+        timer_hz: u32,
+    ) -> Result<HaltReason, ChipError> {
+        assert!(instructions_per_second > 0 && timer_hz > 0);
+        let step_interval = Duration::from_secs(1) / instructions_per_second;
+        let tick_interval = Duration::from_secs(1) / timer_hz;
+
+        let start = Instant::now();
+        let (mut next_step, mut next_tick) = (start, start + tick_interval);
+        loop {
+            let now = Instant::now();
+            if now < next_step {
+                thread::sleep(next_step - now);
+            }
+            while Instant::now() >= next_tick {
+                self.tick_timers();
+                next_tick += tick_interval;
+            }
 
-        op_byte1 << 8 | op_byte2
+            match self.step_unless_breakpoint()? {
+                HaltReason::Running => next_step += step_interval,
+                reason => return Ok(reason),
+            }
+        }
     }
 
-    fn call(&mut self, nnn: u16) {
-        let sp = self.stack_pointer;
-        let stack = &mut self.stack;
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
 
-        if sp > stack.len() {
-            panic!("Stack overflow");
+    pub fn remove_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.remove(&addr);
+    }
+
+    fn step_unless_breakpoint(&mut self) -> Result<HaltReason, ChipError> {
+        if self.breakpoints.contains(&self.position_in_memory) {
+            return Ok(HaltReason::Breakpoint(self.position_in_memory));
         }
+        self.step()
+    }
 
-        stack[sp] = self.position_in_memory as u16;
-        self.stack_pointer += 1;
-        self.position_in_memory = nnn as usize;
+    // Like step, except that a 2NNN is run through to the matching return, so the whole
+    // subroutine goes by as one step and the CPU ends up on the instruction after the
+    // call. The return is spotted by the stack pointer coming back down to its depth at
+    // the call. Anything else that stops run, like a breakpoint in the subroutine, stops
+    // this too.
+    pub fn step_over(&mut self) -> Result<HaltReason, ChipError> {
+        let is_call = matches!(decode(self.read_opcode()?), Instruction::Call(_));
+        let depth = self.stack_pointer;
+
+        let mut reason = self.step()?;
+        while is_call && reason == HaltReason::Running && self.stack_pointer > depth {
+            reason = self.step_unless_breakpoint()?;
+        }
+        Ok(reason)
     }
 
-    fn ret(&mut self) {
-        if self.stack_pointer == 0 {
-            panic!("Stack underflow");
+    // One 60Hz frame: execute up to `cycles` instructions, stopping early if the program
+    // halts or waits for a key (or, with the display_wait quirk, reaches a second draw),
+    // then tick the timers and hand the CPU to the on_frame callback. Returns the state
+    // the last instruction left the CPU in.
+    pub fn run_frame(&mut self, cycles: usize) -> Result<HaltReason, ChipError> {
+        let mut reason = HaltReason::Running;
+        let mut drew = false;
+        for _ in 0..cycles {
+            let is_draw = self.quirks.display_wait
+                && matches!(decode(self.read_opcode()?), Instruction::Draw { .. });
+            if is_draw && drew {
+                break;
+            }
+            drew |= is_draw;
+
+            reason = self.step()?;
+            if reason != HaltReason::Running {
+                break;
+            }
         }
 
-        self.stack_pointer -= 1;
-        let addr = self.stack[self.stack_pointer];
-        self.position_in_memory = addr as usize;
+        self.tick_timers();
+        self.update_fade();
+        // taken out for the call, since the callback can't hold a reference to itself
+        if let Some(mut on_frame) = self.on_frame.take() {
+            on_frame(self);
+            self.on_frame = Some(on_frame);
+        }
+        Ok(reason)
     }
 
-    fn add_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
+    // called at the end of every run_frame, e.g. to render the screen and poll input
+    pub fn set_on_frame(&mut self, callback: impl FnMut(&CPU) + 'static) {
+        self.on_frame = Some(Box::new(callback));
+    }
 
-        let (val, overflow) = arg1.overflowing_add(arg2);
+    // fetch, decode and execute a single instruction
+    pub fn step(&mut self) -> Result<HaltReason, ChipError> {
+        if self.history.capacity() > 0 {
+            self.history.push(self.state());
+        }
 
-        self.registers[x as usize] = val;
+        if self.watchpoints.is_empty() {
+            return self.execute();
+        }
 
-        if overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
+        let before: Vec<u8> = self.watchpoints.iter().map(|&w| self.watched(w)).collect();
+        let reason = self.execute()?;
+        for (&location, &old) in self.watchpoints.iter().zip(&before) {
+            let new = self.watched(location);
+            if new != old {
+                return Ok(HaltReason::Watchpoint { location, old, new });
+            }
         }
+        Ok(reason)
     }
-}
 
-// CHIP-8 program that multiplies by 2 by repeating addition twice
-pub fn example() {
-    let mut cpu = CPU::new();
+    // step, also returning the instruction that was executed, for debuggers that show it
+    pub fn step_verbose(&mut self) -> Result<(Instruction, HaltReason), ChipError> {
+        let instruction = decode(self.read_opcode()?);
+        Ok((instruction, self.step()?))
+    }
 
-    cpu.registers[0] = 5;
-    cpu.registers[1] = 10;
+    // stop with HaltReason::Watchpoint whenever an instruction changes Vx or the byte at
+    // `addr`. Only the value is compared, so writing what was already there doesn't count.
+    pub fn watch_register(&mut self, reg: u8) {
+        self.watch(Watched::Register(reg & 0xF));
+    }
 
-    let mem = &mut cpu.memory;
-    mem[0x000] = 0x21;
-    mem[0x001] = 0x00;
-    mem[0x002] = 0x21;
-    mem[0x003] = 0x00;
-    mem[0x004] = 0x00;
-    mem[0x005] = 0x00;
+    pub fn watch_memory(&mut self, addr: usize) {
+        if addr < self.memory.len() {
+            self.watch(Watched::Memory(addr));
+        }
+    }
 
-    mem[0x100] = 0x80;
-    mem[0x101] = 0x14;
-    mem[0x102] = 0x80;
-    mem[0x103] = 0x14;
-    mem[0x104] = 0x00;
-    mem[0x105] = 0xEE;
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
 
-    cpu.run();
+    fn watch(&mut self, location: Watched) {
+        if !self.watchpoints.contains(&location) {
+            self.watchpoints.push(location);
+        }
+    }
 
-    assert_eq!(cpu.registers[0], 45);
-    println!("5 + (10 * 2) + (10 * 2) = {}", cpu.registers[0]);
+    fn watched(&self, location: Watched) -> u8 {
+        match location {
+            Watched::Register(x) => self.get_register(x),
+            Watched::Memory(addr) => self.memory[addr],
+        }
+    }
+
+    fn execute(&mut self) -> Result<HaltReason, ChipError> {
+        let addr = self.position_in_memory;
+        let opcode = self.read_opcode()?;
+        self.advance_pc(2);
+        self.instruction_count += 1;
+        self.coverage.insert(opcode);
+        if self.profiling {
+            *self.profile.entry((opcode >> 12) as u8).or_insert(0) += 1;
+        }
+
+        let instruction = decode(opcode);
+        if self.mode == Mode::Chip8 && is_super_chip_only(instruction) {
+            return Err(match opcode & 0xF000 {
+                0 => ChipError::UnsupportedSysCall(opcode & 0x0FFF),
+                _ => ChipError::UnknownOpcode { opcode, addr },
+            });
+        }
+        if self.mode != Mode::XoChip && is_xo_chip_only(instruction) {
+            return Err(ChipError::UnknownOpcode { opcode, addr });
+        }
+
+        match instruction {
+            Instruction::Halt | Instruction::Exit => return Ok(HaltReason::Halted),
+            Instruction::ClearScreen => self.clear_screen(),
+            Instruction::Return => self.ret()?,
+            Instruction::ScrollDown(n) => self.scroll_down(n as usize),
+            Instruction::ScrollRight => self.scroll_right(4),
+            Instruction::ScrollLeft => self.scroll_left(4),
+            Instruction::LowRes => self.set_hires(false),
+            Instruction::HighRes => self.set_hires(true),
+            Instruction::Jump(nnn) => self.position_in_memory = nnn as usize,
+            Instruction::Call(nnn) => self.call(nnn)?,
+            Instruction::SkipEqImm { x, nn } => self.skip_if(self.get_register(x) == nn),
+            Instruction::SkipNeImm { x, nn } => self.skip_if(self.get_register(x) != nn),
+            Instruction::SkipEqXY { x, y } => {
+                self.skip_if(self.get_register(x) == self.get_register(y))
+            }
+            Instruction::SkipNeXY { x, y } => {
+                self.skip_if(self.get_register(x) != self.get_register(y))
+            }
+            Instruction::LoadImm { x, nn } => self.set_register(x, nn),
+            // 7XNN wraps around without touching VF, unlike 8XY4
+            Instruction::AddImm { x, nn } => {
+                self.set_register(x, self.get_register(x).wrapping_add(nn))
+            }
+            Instruction::LoadXY { x, y } => self.set_register(x, self.get_register(y)),
+            // the logic ops leave VF alone, as from SUPER-CHIP on; the COSMAC VIP's reset it
+            Instruction::OrXY { x, y } => {
+                self.set_register(x, self.get_register(x) | self.get_register(y))
+            }
+            Instruction::AndXY { x, y } => {
+                self.set_register(x, self.get_register(x) & self.get_register(y))
+            }
+            Instruction::XorXY { x, y } => {
+                self.set_register(x, self.get_register(x) ^ self.get_register(y))
+            }
+            Instruction::AddXY { x, y } => self.add_xy(x, y),
+            Instruction::SubXY { x, y } => {
+                let (val, flag) = sub_with_flag(self.get_register(x), self.get_register(y));
+                self.set_register(x, val);
+                self.set_reg(FLAG, flag);
+            }
+            Instruction::ShrXY { x, y } => {
+                let (val, flag) = shr_with_flag(self.shift_input(x, y));
+                self.set_register(x, val);
+                self.set_reg(FLAG, flag);
+            }
+            Instruction::ShlXY { x, y } => {
+                let (val, flag) = shl_with_flag(self.shift_input(x, y));
+                self.set_register(x, val);
+                self.set_reg(FLAG, flag);
+            }
+            Instruction::SubnXY { x, y } => {
+                let (val, flag) = sub_with_flag(self.get_register(y), self.get_register(x));
+                self.set_register(x, val);
+                self.set_reg(FLAG, flag);
+            }
+            Instruction::LoadIndex(nnn) => self.index = nnn,
+            Instruction::JumpV0(nnn) => self.jump_v0(nnn),
+            Instruction::Random { x, nn } => {
+                let byte = self.rng.next_byte();
+                self.set_register(x, byte & nn)
+            }
+            Instruction::Draw { x, y, n } => self.draw_sprite(x, y, n),
+            Instruction::SkipKeyPressed { x } => self.skip_if(self.key_down(x)),
+            Instruction::SkipKeyNotPressed { x } => self.skip_if(!self.key_down(x)),
+            Instruction::LoadDelay { x } => self.set_register(x, self.delay_timer),
+            Instruction::WaitKey { x } => return Ok(self.wait_key(x)),
+            Instruction::SetDelay { x } => self.delay_timer = self.get_register(x),
+            Instruction::SetSound { x } => self.sound_timer = self.get_register(x),
+            Instruction::AddIndex { x } => {
+                self.index = self.index.wrapping_add(self.get_register(x) as u16);
+                if self.quirks.fx1e_sets_vf {
+                    self.set_reg(FLAG, (self.index > 0xFFF) as u8);
+                }
+            }
+            Instruction::SelectPlanes(n) => self.planes = n & ((1 << PLANES) - 1),
+            Instruction::LoadFont { x } => {
+                let digit = (self.get_register(x) & 0xF) as usize;
+                self.index = (self.font_address + digit * 5) as u16;
+            }
+            Instruction::LoadLargeFont { x } => {
+                let digit = (self.get_register(x) & 0xF) as usize;
+                self.index = (LARGE_FONT_ADDRESS + digit * 10) as u16;
+            }
+            Instruction::StoreBcd { x } => self.store_bcd(x),
+            Instruction::StoreRegs { x } => self.store_regs(x),
+            Instruction::LoadRegs { x } => self.load_regs(x),
+            Instruction::SaveFlags { x } => self.save_flags(x),
+            Instruction::LoadFlags { x } => self.load_flags(x),
+            // 0NNN jumped into native code on the original interpreter's host CPU (an RCA
+            // 1802 on the COSMAC VIP). There's no such code to run here, so it can't be
+            // emulated; reporting it is more useful than silently skipping it.
+            Instruction::SysCall(nnn) => return Err(ChipError::UnsupportedSysCall(nnn)),
+            Instruction::Unknown(opcode) => return Err(ChipError::UnknownOpcode { opcode, addr }),
+        }
+
+        Ok(HaltReason::Running)
+    }
+
+    // replace the source of CXNN's random numbers, e.g. with a SeededRng for
+    // reproducible runs
+    pub fn set_random_source(&mut self, rng: impl RandomSource + 'static) {
+        self.rng = Box::new(rng);
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keys.assign((key & 0xF) as usize, pressed);
+    }
+
+    // count both timers down by one; the host should call this 60 times a second. The
+    // beep plays for as long as the sound timer is non-zero.
+    pub fn tick_timers(&mut self) {
+        self.set_beeping(self.sound_timer > 0);
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // called with true when the beep should start and false when it should stop, so a
+    // host audio backend only has to react to changes
+    pub fn set_on_beep(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.on_beep = Some(Box::new(callback));
+    }
+
+    fn set_beeping(&mut self, beeping: bool) {
+        if beeping == self.beeping {
+            return;
+        }
+
+        self.beeping = beeping;
+        if let Some(on_beep) = &mut self.on_beep {
+            on_beep(beeping);
+        }
+    }
+
+    // replace the whole keypad state at once, e.g. from a snapshot of the host keyboard
+    pub fn set_keys(&mut self, pressed: [bool; 16]) {
+        self.keys = BitSet16::from(pressed);
+    }
+
+    pub fn clear_keys(&mut self) {
+        self.keys = BitSet16::new();
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // the dimensions of the display in the current resolution
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    // the raw frame buffer, one row per element. Only the top-left width() x height()
+    // pixels are on screen, and each pixel is a bitmask of the planes it is lit in (so
+    // just 0 or 1 outside of XO-CHIP).
+    pub fn display(&self) -> &[[u8; HIRES_WIDTH]; HIRES_HEIGHT] {
+        &self.display
+    }
+
+    // whether the pixel at (x, y) is lit in any plane
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height() && self.display[y][x] != 0
+    }
+
+    // the visible screen as text, one line per row, '#' for a lit pixel and '.' otherwise
+    pub fn render_to_string(&self) -> String {
+        let mut out = String::with_capacity((self.width() + 1) * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                out.push(if self.pixel(x, y) { '#' } else { '.' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // The visible screen as a binary PPM (P6) image, white for lit pixels and black
+    // otherwise, with each pixel blown up into a scale x scale square. PPM is just a
+    // short text header followed by raw RGB bytes, so it needs no image library.
+    pub fn export_ppm(&self, scale: usize) -> Vec<u8> {
+        let (width, height) = (self.width() * scale, self.height() * scale);
+        let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        ppm.reserve(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let level = if self.pixel(x / scale, y / scale) {
+                    255
+                } else {
+                    0
+                };
+                ppm.extend_from_slice(&[level; 3]);
+            }
+        }
+        ppm
+    }
+
+    // Keep a brightness for every pixel that run_frame updates, so a frontend can draw
+    // pixels that were just erased as fading out instead of vanishing. This hides most
+    // of the flicker from programs that erase and redraw sprites with XOR every frame.
+    // `rate` is how much an unlit pixel dims per frame; 255 makes it go out at once.
+    pub fn enable_fade(&mut self, rate: u8) {
+        self.fade_rate = rate;
+        self.fade.get_or_insert([[0; HIRES_WIDTH]; HIRES_HEIGHT]);
+    }
+
+    pub fn disable_fade(&mut self) {
+        self.fade = None;
+    }
+
+    // the brightness of every pixel as of the last frame, laid out like display(), or
+    // None unless fading is enabled
+    pub fn render_intensity(&self) -> Option<&[[u8; HIRES_WIDTH]; HIRES_HEIGHT]> {
+        self.fade.as_ref()
+    }
+
+    fn update_fade(&mut self) {
+        let Some(fade) = &mut self.fade else {
+            return;
+        };
+        for (fade_row, row) in fade.iter_mut().zip(&self.display) {
+            for (level, &pixel) in fade_row.iter_mut().zip(row) {
+                *level = if pixel != 0 {
+                    255
+                } else {
+                    level.saturating_sub(self.fade_rate)
+                };
+            }
+        }
+    }
+
+    // bitmask of the planes selected by FN01; always just plane 0 outside of XO-CHIP
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    // whether the screen has changed since the last call, for hosts that only want to
+    // repaint when something was drawn
+    pub fn take_display_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.display_dirty, false)
+    }
+
+    // the return addresses on the stack, innermost call first: each is the instruction
+    // after a CALL that hasn't returned yet
+    pub fn backtrace(&self) -> Vec<u16> {
+        self.stack[..self.stack_pointer]
+            .iter()
+            .rev()
+            .copied()
+            .collect()
+    }
+
+    // number of opcodes fetched so far, including the one that halted the CPU
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    // the distinct opcodes this CPU has executed, for checking what a test ROM exercises
+    pub fn opcode_coverage(&self) -> &HashSet<u16> {
+        &self.coverage
+    }
+
+    // start counting executions per instruction family (the opcode's high nibble). This
+    // costs a hash map update per instruction, so it's off until asked for.
+    pub fn enable_profiling(&mut self) {
+        self.profiling = true;
+    }
+
+    pub fn profile(&self) -> &HashMap<u8, u64> {
+        &self.profile
+    }
+
+    pub fn set_pc_overflow_policy(&mut self, policy: PcOverflowPolicy) {
+        self.on_pc_overflow = policy;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn read_opcode(&self) -> Result<u16, ChipError> {
+        let p = self.position_in_memory;
+        let len = self.memory.len();
+        if p + 1 >= len && self.on_pc_overflow == PcOverflowPolicy::Error {
+            return Err(ChipError::PcOutOfBounds(p));
+        }
+
+        let op_byte1 = self.memory[p % len] as u16;
+        let op_byte2 = self.memory[(p + 1) % len] as u16;
+
+        Ok(op_byte1 << 8 | op_byte2)
+    }
+
+    fn call(&mut self, nnn: u16) -> Result<(), ChipError> {
+        let sp = self.stack_pointer;
+        let stack = &mut self.stack;
+
+        if sp >= stack.len() {
+            return Err(ChipError::StackOverflow);
+        }
+
+        stack[sp] = self.position_in_memory as u16;
+        self.stack_pointer += 1;
+        self.position_in_memory = nnn as usize;
+        Ok(())
+    }
+
+    fn ret(&mut self) -> Result<(), ChipError> {
+        if self.stack_pointer == 0 {
+            return Err(ChipError::StackUnderflow);
+        }
+
+        self.stack_pointer -= 1;
+        let addr = self.stack[self.stack_pointer];
+        self.position_in_memory = addr as usize;
+        Ok(())
+    }
+
+    fn add_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.get_register(x);
+        let arg2 = self.get_register(y);
+
+        let (val, overflow) = arg1.overflowing_add(arg2);
+
+        self.set_register(x, val);
+
+        if overflow {
+            self.set_reg(FLAG, 1);
+        } else {
+            self.set_reg(FLAG, 0);
+        }
+    }
+
+    // the register 8XY6 and 8XYE shift, which depends on the shift_uses_vy quirk
+    fn shift_input(&self, x: u8, y: u8) -> u8 {
+        self.get_register(if self.quirks.shift_uses_vy { y } else { x })
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.advance_pc(2);
+        }
+    }
+
+    // BNNN: jump to NNN + V0. That can land past the end of memory, where it wraps or
+    // fails on the next fetch according to the PC overflow policy.
+    fn jump_v0(&mut self, nnn: u16) {
+        self.position_in_memory = nnn as usize;
+        self.advance_pc(self.get_register(0) as usize);
+    }
+
+    fn advance_pc(&mut self, by: usize) {
+        self.position_in_memory += by;
+        if self.on_pc_overflow == PcOverflowPolicy::WrapAround {
+            self.position_in_memory %= self.memory.len();
+        }
+    }
+
+    // 00E0 only clears the selected planes
+    fn clear_screen(&mut self) {
+        for pixel in self.display.iter_mut().flatten() {
+            *pixel &= !self.planes;
+        }
+        self.display_dirty = true;
+    }
+
+    // DXYN: XOR an N-row sprite read from memory at I onto the screen at (Vx, Vy), setting
+    // VF if any pixel was switched off. The start position wraps around the screen but the
+    // sprite itself is clipped at the edges. In SUPER-CHIP and XO-CHIP modes DXY0 draws a
+    // 16x16 sprite made of 32 bytes, two per row. With more than one plane selected, each
+    // plane gets its own copy of the sprite data, one after the other starting at I.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) {
+        let (width, height) = (self.width(), self.height());
+        let x0 = self.get_register(x) as usize % width;
+        let y0 = self.get_register(y) as usize % height;
+
+        let (sprite_width, rows) = match (self.mode, n) {
+            (Mode::SuperChip | Mode::XoChip, 0) => (16, 16),
+            _ => (8, n as usize),
+        };
+        let bytes_per_row = sprite_width / 8;
+
+        let mut collision = false;
+        let mut addr = self.index as usize;
+        let selected = self.planes;
+        for plane in (0..PLANES).filter(|plane| selected & (1 << plane) != 0) {
+            let plane_bit = 1 << plane;
+
+            for row in 0..rows {
+                for byte in 0..bytes_per_row {
+                    let bits = self.load_byte(addr);
+                    addr += 1;
+
+                    let py = y0 + row;
+                    if py >= height {
+                        continue;
+                    }
+
+                    for bit in 0..8 {
+                        let px = x0 + byte * 8 + bit;
+                        if px >= width {
+                            break;
+                        }
+
+                        if bits & (0x80 >> bit) != 0 {
+                            let pixel = &mut self.display[py][px];
+                            collision |= *pixel & plane_bit != 0;
+                            *pixel ^= plane_bit;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.set_reg(FLAG, collision as u8);
+        self.display_dirty = true;
+    }
+
+    // 00CN, 00FB and 00FC shift the selected planes of the visible screen, filling the gap
+    // with blank pixels
+    fn scroll_down(&mut self, n: usize) {
+        let (width, height, planes) = (self.width(), self.height(), self.planes);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let shifted = if y >= n { self.display[y - n][x] } else { 0 };
+                self.display[y][x] = (self.display[y][x] & !planes) | (shifted & planes);
+            }
+        }
+        self.display_dirty = true;
+    }
+
+    fn scroll_right(&mut self, n: usize) {
+        let (width, height, planes) = (self.width(), self.height(), self.planes);
+        for row in self.display.iter_mut().take(height) {
+            for x in (0..width).rev() {
+                let shifted = if x >= n { row[x - n] } else { 0 };
+                row[x] = (row[x] & !planes) | (shifted & planes);
+            }
+        }
+        self.display_dirty = true;
+    }
+
+    fn scroll_left(&mut self, n: usize) {
+        let (width, height, planes) = (self.width(), self.height(), self.planes);
+        for row in self.display.iter_mut().take(height) {
+            for x in 0..width {
+                let shifted = if x + n < width { row[x + n] } else { 0 };
+                row[x] = (row[x] & !planes) | (shifted & planes);
+            }
+        }
+        self.display_dirty = true;
+    }
+
+    // 00FE and 00FF switch resolution, which also clears every plane of the screen
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.display_dirty = true;
+    }
+
+    // a load by an instruction, wrapping at the end of memory and going through the
+    // peripheral if the address is mapped
+    fn load_byte(&mut self, addr: usize) -> u8 {
+        let addr = addr % self.memory.len();
+        if let Some((region, peripheral)) = &mut self.peripheral {
+            if region.contains(&addr) {
+                if let Some(value) = peripheral.on_read(addr) {
+                    return value;
+                }
+            }
+        }
+        self.memory[addr]
+    }
+
+    fn store_byte(&mut self, addr: usize, value: u8) {
+        let addr = addr % self.memory.len();
+        if let Some((region, peripheral)) = &mut self.peripheral {
+            if region.contains(&addr) {
+                peripheral.on_write(addr, value);
+            }
+        }
+        self.memory[addr] = value;
+    }
+
+    // FX55: store V0..=Vx at I onwards. I is left unchanged, as on SUPER-CHIP and later
+    // interpreters; the COSMAC VIP advanced it past the stored bytes.
+    fn store_regs(&mut self, x: u8) {
+        for i in 0..=x as usize {
+            self.store_byte(self.index as usize + i, self.get_register(i as u8));
+        }
+    }
+
+    // FX65: load V0..=Vx from I onwards, leaving I unchanged like FX55
+    fn load_regs(&mut self, x: u8) {
+        for i in 0..=x as usize {
+            let value = self.load_byte(self.index as usize + i);
+            self.set_register(i as u8, value);
+        }
+    }
+
+    // FX33: store the decimal digits of Vx at I, I+1 and I+2, hundreds first
+    fn store_bcd(&mut self, x: u8) {
+        let value = self.get_register(x);
+        let addr = self.index as usize;
+        self.store_byte(addr, value / 100);
+        self.store_byte(addr + 1, value / 10 % 10);
+        self.store_byte(addr + 2, value % 10);
+    }
+
+    // FX75 and FX85 copy V0..=Vx to and from the RPL flags. There are only 8 flags, so x
+    // is clamped to 7.
+    fn save_flags(&mut self, x: u8) {
+        let n = x.min(7) as usize + 1;
+        self.rpl_flags[..n].copy_from_slice(&self.registers[..n]);
+    }
+
+    fn load_flags(&mut self, x: u8) {
+        let n = x.min(7) as usize + 1;
+        self.registers[..n].copy_from_slice(&self.rpl_flags[..n]);
+    }
+
+    // whether the key named by the low nibble of Vx is held down
+    fn key_down(&self, x: u8) -> bool {
+        self.keys.get((self.get_register(x) & 0xF) as usize)
+    }
+
+    // FX0A: block until a key is pressed, then store it in Vx. Blocking is done by
+    // stepping the PC back onto this instruction and reporting the wait to the caller.
+    fn wait_key(&mut self, x: u8) -> HaltReason {
+        match self.keys.iter_set().next() {
+            Some(key) => {
+                self.set_register(x, key as u8);
+                HaltReason::Running
+            }
+            None => {
+                // the fetch may have wrapped the PC around past 0
+                let len = self.memory.len();
+                self.position_in_memory = (self.position_in_memory + len - 2) % len;
+                HaltReason::WaitingForKey(x)
+            }
+        }
+    }
+}
+
+// Sprite data as text art, '#' for each set bit and ' ' for each clear one, a line per
+// row. Ordinary sprites are a byte (8 pixels) wide with one row per byte; SUPER-CHIP's
+// 16x16 sprites take two bytes per row, 32 bytes in all.
+pub fn render_sprite(bytes: &[u8]) -> String {
+    render_sprite_rows(bytes, 1)
+}
+
+pub fn render_large_sprite(bytes: &[u8]) -> String {
+    render_sprite_rows(bytes, 2)
+}
+
+fn render_sprite_rows(bytes: &[u8], bytes_per_row: usize) -> String {
+    let mut out = String::new();
+    for row in bytes.chunks(bytes_per_row) {
+        for byte in row {
+            for bit in (0..8).rev() {
+                out.push(if byte & (1 << bit) != 0 { '#' } else { ' ' });
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Nearest-neighbour scaling of a grid of pixels by a whole number, each pixel becoming
+// a factor x factor block, for hosts that draw the screen at a larger size. The result
+// is factor times as many rows, each factor times as long. Works for either resolution.
+pub fn upscale<const W: usize, const H: usize>(
+    display: &[[bool; W]; H],
+    factor: usize,
+) -> Vec<Vec<bool>> {
+    display
+        .iter()
+        .flat_map(|row| {
+            let scaled: Vec<bool> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, factor))
+                .collect();
+            std::iter::repeat_n(scaled, factor)
+        })
+        .collect()
+}
+
+pub fn upscale_example() {
+    let mut display = [[false; LORES_WIDTH]; LORES_HEIGHT];
+    display[0][0] = true;
+    display[31][63] = true;
+
+    let scaled = upscale(&display, 2);
+    assert_eq!(scaled.len(), 64);
+    assert!(scaled.iter().all(|row| row.len() == 128));
+    for (y, x) in [(0, 0), (31, 63), (5, 9)] {
+        for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            assert_eq!(scaled[y * 2 + dy][x * 2 + dx], display[y][x]);
+        }
+    }
+    assert_eq!(scaled.iter().flatten().filter(|&&pixel| pixel).count(), 8);
+    println!("upscaled 64x32 to {}x{}", scaled[0].len(), scaled.len());
+}
+
+// CHIP-8 only says whether the buzzer is on, not what it sounds like; a square wave
+// around this pitch is the usual choice
+pub const BEEP_FREQUENCY: f32 = 440.0;
+
+// `duration` of a square wave at `freq` Hz, as samples in [-1, 1] for an audio device
+// running at `sample_rate`. A host can queue these while the sound timer is non-zero.
+pub fn beep_samples(sample_rate: u32, duration: Duration, freq: f32) -> Vec<f32> {
+    let count = (sample_rate as f64 * duration.as_secs_f64()).round() as usize;
+    let cycles_per_sample = freq as f64 / sample_rate as f64;
+
+    (0..count)
+        .map(|i| {
+            // how far through the current cycle this sample falls: high for the first
+            // half, low for the second
+            let phase = (i as f64 * cycles_per_sample).fract();
+            if phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        })
+        .collect()
+}
+
+// The COSMAC VIP's hex keypad, and the block of keys on the left of a QWERTY keyboard
+// that emulators conventionally map it to, key for key:
+//
+//     1 2 3 C        1 2 3 4
+//     4 5 6 D        q w e r
+//     7 8 9 E        a s d f
+//     A 0 B F        z x c v
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+const HOST_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', '4'],
+    ['q', 'w', 'e', 'r'],
+    ['a', 's', 'd', 'f'],
+    ['z', 'x', 'c', 'v'],
+];
+
+// (CHIP-8 key, host key) for each position on the keypad
+fn keypad_pairs() -> impl Iterator<Item = (u8, char)> {
+    let keys = KEYPAD.as_flattened().iter().copied();
+    keys.zip(HOST_KEYS.as_flattened().iter().copied())
+}
+
+// the CHIP-8 key a host key maps to, ignoring case
+pub fn host_key_to_chip8(c: char) -> Option<u8> {
+    let c = c.to_ascii_lowercase();
+    keypad_pairs()
+        .find(|&(_, host)| host == c)
+        .map(|(key, _)| key)
+}
+
+// the (lowercase) host key for the CHIP-8 key in the low nibble of `k`
+pub fn chip8_to_host_key(k: u8) -> char {
+    keypad_pairs()
+        .find(|&(key, _)| key == k & 0xF)
+        .map(|(_, host)| host)
+        .expect("every hex digit is on the keypad")
+}
+
+// seed for the default random source, so that unseeded CPUs differ from run to run
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn put_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    out.extend_from_slice(field);
+}
+
+// reads back the fields written by put_field, checking each has the expected length
+struct SaveReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl SaveReader<'_> {
+    fn next<const N: usize>(&mut self) -> Result<[u8; N], ChipError> {
+        let truncated = ChipError::InvalidSaveState("truncated");
+        let (len, rest) = self.bytes.split_first_chunk::<4>().ok_or(truncated)?;
+        if u32::from_le_bytes(*len) as usize != N {
+            return Err(ChipError::InvalidSaveState("field has the wrong length"));
+        }
+        let (field, rest) = rest.split_first_chunk::<N>().ok_or(truncated)?;
+        self.bytes = rest;
+        Ok(*field)
+    }
+}
+
+// Testing utility: run a pseudo-random ROM of `len` bytes generated from `seed` (which
+// also seeds CXNN) for at most `max_steps` instructions, returning the final state.
+// Random bytes rarely make a program that halts cleanly, so most seeds end in an Err.
+pub fn run_random_program(seed: u64, len: usize, max_steps: u64) -> Result<CpuState, ChipError> {
+    let mut rng = SeededRng::new(seed);
+    let rom: Vec<u8> = (0..len).map(|_| rng.next_byte()).collect();
+
+    let mut cpu = CPU::new();
+    cpu.set_random_source(rng);
+    cpu.load_rom(&rom)?;
+    cpu.run_bounded(max_steps)?;
+
+    Ok(cpu.state())
+}
+
+// how long run_test_rom lets a test ROM run; plenty for the usual opcode and flag tests
+const TEST_ROM_STEPS: u64 = 100_000;
+
+// Testing utility: run a test ROM and compare the screen it leaves with
+// `expected_screen`, in render_to_string's format (surrounding blank lines are
+// ignored). Test ROMs generally draw their results and then loop forever, so running
+// out of steps counts as finishing, but any other error fails the test.
+pub fn run_test_rom(rom: &[u8], expected_screen: &str) -> bool {
+    let mut cpu = CPU::new();
+    if cpu.load_rom(rom).is_err() {
+        return false;
+    }
+
+    match cpu.run_bounded(TEST_ROM_STEPS) {
+        Ok(_) | Err(ChipError::StepLimitExceeded) => {}
+        Err(_) => return false,
+    }
+    cpu.render_to_string().trim() == expected_screen.trim()
+}
+
+pub fn test_rom_example() {
+    // A050: I = the "0" glyph, D005: draw it at (V0, V0) = (0, 0), 6108: V1 = 8,
+    // A055: I = the "1" glyph, D105: draw it at (8, 0), 120A: loop forever
+    let rom = [
+        0xA0, 0x50, 0xD0, 0x05, 0x61, 0x08, 0xA0, 0x55, 0xD1, 0x05, 0x12, 0x0A,
+    ];
+
+    let glyphs = [
+        "####......#.....",
+        "#..#.....##.....",
+        "#..#......#.....",
+        "#..#......#.....",
+        "####.....###....",
+    ];
+    let mut expected = String::new();
+    for row in 0..LORES_HEIGHT {
+        let drawn = glyphs.get(row).copied().unwrap_or("");
+        expected.push_str(&format!("{:.<width$}\n", drawn, width = LORES_WIDTH));
+    }
+
+    assert!(run_test_rom(&rom, &expected));
+    assert!(!run_test_rom(&rom, &expected.replacen('#', ".", 1)));
+    // E123 isn't an opcode, so this one fails however the screen looks
+    assert!(!run_test_rom(&[0xE1, 0x23], &".".repeat(LORES_WIDTH)));
+    println!(
+        "test ROM drew:\n{}",
+        expected.lines().take(5).collect::<Vec<_>>().join("\n")
+    );
+}
+
+// one line per register in hex and decimal, for logging between instructions. VF is
+// marked since it's the flag register that arithmetic and drawing overwrite.
+pub fn dump_registers(cpu: &CPU) -> String {
+    let mut out = String::new();
+    for (i, v) in cpu.registers.iter().enumerate() {
+        out.push_str(&format!("V{:X} = {:#04X} ({:>3})", i, v, v));
+        if i == 0xF {
+            out.push_str("  <- flag");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// registers in hex and the stack up to the stack pointer. Memory is 4KiB, so it is
+// only summarised by how much of it is in use.
+impl fmt::Debug for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU {{ ")?;
+        for (i, v) in self.registers.iter().enumerate() {
+            write!(f, "V{:X}={:#04X} ", i, v)?;
+        }
+        write!(
+            f,
+            "PC={:#05X} I={:#05X} SP={} DT={} ST={} stack={:03X?} ",
+            self.position_in_memory,
+            self.index,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+            &self.stack[..self.stack_pointer.min(self.stack.len())],
+        )?;
+        let nonzero = self.memory.iter().filter(|&&byte| byte != 0).count();
+        write!(
+            f,
+            "memory=[{} of {} bytes nonzero] }}",
+            nonzero,
+            self.memory.len()
+        )
+    }
+}
+
+impl fmt::Display for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.render_to_string())
+    }
+}
+
+// CHIP-8 program that multiplies by 2 by repeating addition twice
+pub fn example() {
+    let mut cpu = CPU::new();
+
+    cpu.registers[0] = 5;
+    cpu.registers[1] = 10;
+
+    let mem = &mut cpu.memory;
+    mem[0x000] = 0x21;
+    mem[0x001] = 0x00;
+    mem[0x002] = 0x21;
+    mem[0x003] = 0x00;
+    mem[0x004] = 0x00;
+    mem[0x005] = 0x00;
+
+    mem[0x100] = 0x80;
+    mem[0x101] = 0x14;
+    mem[0x102] = 0x80;
+    mem[0x103] = 0x14;
+    mem[0x104] = 0x00;
+    mem[0x105] = 0xEE;
+
+    assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+    assert_eq!(cpu.registers[0], 45);
+    println!("5 + (10 * 2) + (10 * 2) = {}", cpu.registers[0]);
+}
+
+// records every write it sees, sharing the log so it can be read back after the run
+struct WriteLogger {
+    log: Rc<RefCell<Vec<(usize, u8)>>>,
+}
+
+impl Peripheral for WriteLogger {
+    fn on_write(&mut self, addr: usize, value: u8) {
+        self.log.borrow_mut().push((addr, value));
+    }
+
+    fn on_read(&mut self, _addr: usize) -> Option<u8> {
+        None
+    }
+}
+
+// FX55 storing registers into a memory-mapped region above 0xF00
+pub fn peripheral_example() {
+    let mut cpu = CPU::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    cpu.attach_peripheral(
+        0xF00..0x1000,
+        WriteLogger {
+            log: Rc::clone(&log),
+        },
+    );
+
+    cpu.registers[0] = 0xAB;
+    cpu.registers[1] = 0xCD;
+
+    // AF00: I = 0xF00, F155: store V0..=V1 at I, 0000: halt
+    cpu.load_rom(&[0xAF, 0x00, 0xF1, 0x55, 0x00, 0x00]).unwrap();
+
+    assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+    assert_eq!(*log.borrow(), vec![(0xF00, 0xAB), (0xF01, 0xCD)]);
+    println!("peripheral saw writes {:X?}", log.borrow());
+}
+
+pub fn fade_example() {
+    let mut cpu = CPU::new();
+    cpu.enable_fade(64);
+
+    // A050: I = the "0" glyph, D001: draw its top row, 00E0: clear, 1206: loop forever
+    cpu.load_rom(&[0xA0, 0x50, 0xD0, 0x01, 0x00, 0xE0, 0x12, 0x06])
+        .unwrap();
+
+    cpu.run_frame(2).unwrap();
+    assert_eq!(cpu.render_intensity().unwrap()[0][0], 255);
+
+    let mut levels = Vec::new();
+    for _ in 0..5 {
+        cpu.run_frame(1).unwrap();
+        levels.push(cpu.render_intensity().unwrap()[0][0]);
+    }
+    assert_eq!(levels, vec![191, 127, 63, 0, 0]);
+    println!("erased pixel faded through {:?}", levels);
+}
+
+pub fn pacing_example() {
+    let mut cpu = CPU::new();
+
+    // ten 7001s (V0 += 1) and a halt, the halt being due 10ms in at 1000Hz
+    let mut rom = [0x70, 0x01].repeat(10);
+    rom.extend_from_slice(&[0x00, 0x00]);
+    cpu.load_rom(&rom).unwrap();
+
+    let start = Instant::now();
+    assert_eq!(cpu.run_at_hz(1000, 60), Ok(HaltReason::Halted));
+    let elapsed = start.elapsed();
+
+    assert_eq!(cpu.registers[0], 10);
+    assert!(elapsed >= Duration::from_millis(10));
+    println!("11 instructions at 1000Hz took {:?}", elapsed);
+}
+
+pub fn quirks_example() {
+    // AFFF: I = 0xFFF, 7001: V0 += 1, F01E: I += V0, 0000: halt. VF starts out as 7.
+    let rom = [0xAF, 0xFF, 0x70, 0x01, 0xF0, 0x1E, 0x00, 0x00];
+
+    for fx1e_sets_vf in [false, true] {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(Quirks {
+            fx1e_sets_vf,
+            ..Quirks::default()
+        });
+        cpu.set_reg(FLAG, 7);
+        cpu.load_rom(&rom).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        assert_eq!(cpu.state().index, 0x1000);
+        assert_eq!(cpu.reg(FLAG), if fx1e_sets_vf { 1 } else { 7 });
+    }
+    println!("FX1E past 0xFFF sets VF only with the Amiga quirk");
+}
+
+pub fn subtraction_example() {
+    assert_eq!(sub_with_flag(5, 3), (2, 1));
+    assert_eq!(sub_with_flag(3, 5), (254, 0));
+    assert_eq!(sub_with_flag(7, 7), (0, 1));
+
+    let mut cpu = CPU::new();
+    cpu.registers[0] = 3;
+    cpu.registers[1] = 5;
+    cpu.registers[2] = 3;
+    cpu.registers[3] = 5;
+
+    // 8015: V0 -= V1, which borrows, then 8237: V2 = V3 - V2, which doesn't
+    cpu.load_rom(&[0x80, 0x15, 0x82, 0x37, 0x00, 0x00]).unwrap();
+    assert_eq!(cpu.step(), Ok(HaltReason::Running));
+    assert_eq!((cpu.registers[0], cpu.reg(FLAG)), (254, 0));
+    assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+    assert_eq!((cpu.registers[2], cpu.reg(FLAG)), (2, 1));
+}
+
+pub fn shift_example() {
+    assert_eq!(shr_with_flag(0x05), (0x02, 1));
+    assert_eq!(shr_with_flag(0x04), (0x02, 0));
+    assert_eq!(shl_with_flag(0x80), (0x00, 1));
+    assert_eq!(shl_with_flag(0x41), (0x82, 0));
+
+    // 8016: V0 = V0 >> 1 (V1 >> 1 with the quirk), 802E: V0 = V0 << 1 (V2 << 1)
+    let rom = [0x80, 0x16, 0x80, 0x2E, 0x00, 0x00];
+    for shift_uses_vy in [false, true] {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(Quirks {
+            shift_uses_vy,
+            ..Quirks::default()
+        });
+        cpu.registers[0] = 0x03;
+        cpu.registers[1] = 0x80;
+        cpu.registers[2] = 0x81;
+        cpu.load_rom(&rom).unwrap();
+
+        cpu.step().unwrap();
+        let expected = if shift_uses_vy { (0x40, 0) } else { (0x01, 1) };
+        assert_eq!((cpu.registers[0], cpu.reg(FLAG)), expected);
+
+        cpu.run().unwrap();
+        let expected = if shift_uses_vy { (0x02, 1) } else { (0x02, 0) };
+        assert_eq!((cpu.registers[0], cpu.reg(FLAG)), expected);
+    }
+}
+
+pub fn legal_opcode_example() {
+    // F201: select planes 0 and 1, XO-CHIP only
+    assert!(!is_legal_opcode(0xF201, Mode::Chip8));
+    assert!(!is_legal_opcode(0xF201, Mode::SuperChip));
+    assert!(is_legal_opcode(0xF201, Mode::XoChip));
+
+    // 00FF: high resolution, from SUPER-CHIP on
+    assert!(!is_legal_opcode(0x00FF, Mode::Chip8));
+    assert!(is_legal_opcode(0x00FF, Mode::SuperChip));
+
+    assert!(is_legal_opcode(0x8014, Mode::Chip8));
+    assert!(!is_legal_opcode(0xE123, Mode::XoChip));
+}
+
+pub fn register_index_example() {
+    let mut cpu = CPU::new();
+    cpu.set_register(0xF, 0x42);
+    assert_eq!(cpu.get_register(0x1F), 0x42);
+
+    cpu.set_register(0xF3, 7);
+    assert_eq!(cpu.get_register(3), 7);
+    println!("register 0x1F reads VF = {:#04X}", cpu.get_register(0x1F));
+}
+
+pub fn memory_map_example() {
+    let mut cpu = CPU::new();
+    assert_eq!(cpu.describe_address(0x060), "font");
+    assert_eq!(cpu.describe_address(0x000), "reserved for the interpreter");
+    assert_eq!(cpu.describe_address(0x200), "program and work RAM");
+    assert_eq!(cpu.describe_address(0x1000), "out of bounds");
+
+    cpu.set_font_address(0x300).unwrap();
+    assert_eq!(cpu.describe_address(0x310), "font");
+    assert_eq!(cpu.describe_address(0x060), "reserved for the interpreter");
+
+    for (region, name) in memory_map() {
+        println!("{:#05X}..{:#05X} {}", region.start, region.end, name);
+    }
+}
+
+pub fn display_wait_example() {
+    let mut cpu = CPU::new();
+    cpu.set_quirks(Quirks {
+        display_wait: true,
+        ..Quirks::default()
+    });
+
+    // A050: I = the "0" glyph, D001 twice: draw its top row and erase it again,
+    // 1206: loop forever
+    cpu.load_rom(&[0xA0, 0x50, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06])
+        .unwrap();
+
+    // the second draw has to wait for the next frame, however many cycles are left
+    cpu.run_frame(10).unwrap();
+    assert!(cpu.pixel(0, 0));
+    assert_eq!(cpu.state().pc, 0x204);
+
+    cpu.run_frame(10).unwrap();
+    assert!(!cpu.pixel(0, 0));
+    println!("display_wait spread two draws over two frames");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    #[test]
+    fn examples_run() {
+        example();
+        peripheral_example();
+        fade_example();
+        pacing_example();
+        quirks_example();
+        display_wait_example();
+        memory_map_example();
+        register_index_example();
+        upscale_example();
+        legal_opcode_example();
+        test_rom_example();
+        subtraction_example();
+        shift_example();
+    }
+
+    #[test]
+    fn decode_splits_out_the_operands() {
+        assert_eq!(decode(0x8014), Instruction::AddXY { x: 0, y: 1 });
+        assert_eq!(decode(0x00EE), Instruction::Return);
+        assert_eq!(decode(0x0000), Instruction::Halt);
+        assert_eq!(decode(0x0123), Instruction::SysCall(0x123));
+        assert_eq!(decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(decode(0x3A7F), Instruction::SkipEqImm { x: 0xA, nn: 0x7F });
+        assert_eq!(decode(0xD125), Instruction::Draw { x: 1, y: 2, n: 5 });
+        assert_eq!(decode(0xF933), Instruction::StoreBcd { x: 9 });
+    }
+
+    #[test]
+    fn decode_leaves_unknown_opcodes_whole() {
+        for opcode in [0x5001, 0x800F, 0x9AB1, 0xE123, 0xF0FF] {
+            assert_eq!(decode(opcode), Instruction::Unknown(opcode));
+        }
+    }
+
+    #[test]
+    fn instruction_count_matches_the_example_program() {
+        // example(): two calls to a subroutine doing two adds, then a halt
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[0x000..0x006].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        assert_eq!(cpu.instruction_count(), 0);
+
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        // CALL, ADD, ADD, RET twice over, and the halting 0000
+        assert_eq!(cpu.instruction_count(), 9);
+        assert_eq!(cpu.registers[0], 45);
+    }
+
+    #[test]
+    fn run_reports_why_it_stopped() {
+        // 8014 then 0000
+        let mut cpu = CPU::new();
+        cpu.memory[..4].copy_from_slice(&[0x80, 0x14, 0x00, 0x00]);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        // F30A waits for a key, leaving the PC on the FX0A
+        let mut cpu = CPU::new();
+        cpu.memory[..4].copy_from_slice(&[0xF3, 0x0A, 0x00, 0x00]);
+        assert_eq!(cpu.run(), Ok(HaltReason::WaitingForKey(3)));
+        assert_eq!(cpu.run(), Ok(HaltReason::WaitingForKey(3)));
+        cpu.set_key(9, true);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[3], 9);
+    }
+
+    #[test]
+    fn run_returns_errors_instead_of_panicking() {
+        // 2000: call itself forever
+        let mut cpu = CPU::new();
+        cpu.memory[..2].copy_from_slice(&[0x20, 0x00]);
+        assert_eq!(cpu.run(), Err(ChipError::StackOverflow));
+
+        let mut cpu = CPU::new();
+        cpu.memory[..2].copy_from_slice(&[0x00, 0xEE]);
+        assert_eq!(cpu.run(), Err(ChipError::StackUnderflow));
+
+        let mut cpu = CPU::new();
+        cpu.memory[..2].copy_from_slice(&[0xE1, 0x23]);
+        assert_eq!(
+            cpu.run(),
+            Err(ChipError::UnknownOpcode {
+                opcode: 0xE123,
+                addr: 0
+            })
+        );
+    }
+
+    #[test]
+    fn machine_code_routines_are_reported() {
+        let mut cpu = CPU::new();
+        cpu.memory[..2].copy_from_slice(&[0x01, 0x23]);
+        assert_eq!(cpu.run(), Err(ChipError::UnsupportedSysCall(0x123)));
+    }
+
+    #[test]
+    fn super_chip_scrolling() {
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        // A100, D001: draw a 4 pixel wide row, 00FB: scroll right 4, 00C2: down 2, 00FC: left 4
+        cpu.memory[..12].copy_from_slice(&[
+            0xA1, 0x00, 0xD0, 0x01, 0x00, 0xFB, 0x00, 0xC2, 0x00, 0xFC, 0x00, 0x00,
+        ]);
+        cpu.memory[0x100] = 0xF0;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.display[0][0] == 1 && cpu.display[0][3] == 1 && cpu.display[0][4] == 0);
+        cpu.step().unwrap();
+        assert!(cpu.display[0][3] == 0 && cpu.display[0][4] == 1 && cpu.display[0][7] == 1);
+        cpu.step().unwrap();
+        assert!(cpu.display[0][4] == 0 && cpu.display[2][4] == 1);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert!(cpu.display[2][0] == 1 && cpu.display[2][3] == 1 && cpu.display[2][4] == 0);
+    }
+
+    #[test]
+    fn super_chip_resolution_toggle() {
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        // 00FF: high resolution, 00FE: back to low resolution
+        cpu.memory[..6].copy_from_slice(&[0x00, 0xFF, 0x00, 0xFE, 0x00, 0x00]);
+        assert_eq!((cpu.width(), cpu.height()), (LORES_WIDTH, LORES_HEIGHT));
+        cpu.step().unwrap();
+        assert_eq!((cpu.width(), cpu.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        cpu.step().unwrap();
+        assert_eq!((cpu.width(), cpu.height()), (LORES_WIDTH, LORES_HEIGHT));
+
+        // plain CHIP-8 doesn't know the opcode
+        let mut cpu = CPU::new();
+        cpu.memory[..2].copy_from_slice(&[0x00, 0xFF]);
+        assert_eq!(cpu.run(), Err(ChipError::UnsupportedSysCall(0xFF)));
+    }
+
+    #[test]
+    fn large_font_points_at_a_ten_byte_digit() {
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        // F230: I = large glyph for the digit in V2
+        cpu.memory[..4].copy_from_slice(&[0xF2, 0x30, 0x00, 0x00]);
+        cpu.registers[2] = 3;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.index as usize, LARGE_FONT_ADDRESS + 30);
+        let glyph = &cpu.memory[cpu.index as usize..cpu.index as usize + 10];
+        assert_eq!(
+            glyph,
+            [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C]
+        );
+    }
+
+    #[test]
+    fn rpl_flags_round_trip_registers() {
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        // F975: save V0..V9 (clamped to V7), then F985 to load them back
+        cpu.memory[..4].copy_from_slice(&[0xF9, 0x75, 0x00, 0x00]);
+        for (v, value) in cpu.registers.iter_mut().zip(1..) {
+            *v = value;
+        }
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut cpu2 = CPU::with_mode(Mode::SuperChip);
+        cpu2.memory[..4].copy_from_slice(&[0xF9, 0x85, 0x00, 0x00]);
+        cpu2.rpl_flags = cpu.rpl_flags;
+        assert_eq!(cpu2.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu2.registers[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(cpu2.registers[8..10], [0, 0]);
+    }
+
+    #[test]
+    fn draws_mark_the_display_dirty() {
+        let mut cpu = CPU::new();
+        // A100, D001: draw, 3000: skip the next opcode since V0 == 0
+        cpu.memory[..10]
+            .copy_from_slice(&[0xA1, 0x00, 0xD0, 0x01, 0x30, 0x00, 0xE1, 0x23, 0x00, 0x00]);
+        cpu.memory[0x100] = 0x80;
+        assert!(!cpu.take_display_dirty());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.take_display_dirty());
+        assert!(!cpu.take_display_dirty());
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert!(!cpu.take_display_dirty());
+    }
+
+    #[test]
+    fn drawing_into_plane_1_leaves_plane_0_alone() {
+        let mut cpu = CPU::with_mode(Mode::XoChip);
+        // A100, D001: draw 0xF0 into plane 0, F201: select plane 1 only, A101, D001: draw 0x3C
+        cpu.memory[..12].copy_from_slice(&[
+            0xA1, 0x00, 0xD0, 0x01, 0xF2, 0x01, 0xA1, 0x01, 0xD0, 0x01, 0x00, 0x00,
+        ]);
+        cpu.memory[0x100] = 0xF0;
+        cpu.memory[0x101] = 0x3C;
+        assert_eq!(cpu.selected_planes(), 0b01);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.selected_planes(), 0b10);
+
+        let plane = |bit: u8| (0..8).fold(0u8, |row, x| row << 1 | (cpu.display[0][x] >> bit & 1));
+        assert_eq!(plane(0), 0xF0);
+        assert_eq!(plane(1), 0x3C);
+        assert_eq!(cpu.registers[0xF], 0);
+
+        // plane select is XO-CHIP only
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        cpu.memory[..2].copy_from_slice(&[0xF2, 0x01]);
+        assert_eq!(
+            cpu.run(),
+            Err(ChipError::UnknownOpcode {
+                opcode: 0xF201,
+                addr: 0
+            })
+        );
+    }
+
+    #[test]
+    fn seeded_rng_is_reproducible() {
+        let bytes = |seed| {
+            let mut rng = SeededRng::new(seed);
+            (0..32).map(|_| rng.next_byte()).collect::<Vec<_>>()
+        };
+        assert_eq!(bytes(1), bytes(1));
+        assert_ne!(bytes(1), bytes(2));
+    }
+
+    #[test]
+    fn random_opcode_uses_the_random_source() {
+        let run = |seed| {
+            let mut cpu = CPU::new();
+            cpu.set_random_source(SeededRng::new(seed));
+            // C0FF: V0 = random byte, C10F: V1 = random byte & 0x0F
+            cpu.memory[..6].copy_from_slice(&[0xC0, 0xFF, 0xC1, 0x0F, 0x00, 0x00]);
+            assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+            (cpu.registers[0], cpu.registers[1])
+        };
+        assert_eq!(run(7), run(7));
+        assert!(run(7).1 <= 0x0F);
+    }
+
+    #[test]
+    fn run_bounded_stops_an_infinite_loop() {
+        let mut cpu = CPU::new();
+        // 1000: jump to itself
+        cpu.memory[..2].copy_from_slice(&[0x10, 0x00]);
+        assert_eq!(cpu.run_bounded(100), Err(ChipError::StepLimitExceeded));
+        assert_eq!(cpu.instruction_count(), 100);
+
+        let mut cpu = CPU::new();
+        cpu.memory[..4].copy_from_slice(&[0x80, 0x14, 0x00, 0x00]);
+        assert_eq!(cpu.run_bounded(100), Ok(HaltReason::Halted));
+    }
+
+    #[test]
+    fn from_rom_and_run_never_panics() {
+        assert_eq!(CPU::from_rom_and_run(&[], 10), Ok(HaltReason::Halted));
+        assert_eq!(
+            CPU::from_rom_and_run(&[0x12, 0x00], 10),
+            Err(ChipError::StepLimitExceeded)
+        );
+        assert_eq!(
+            CPU::from_rom_and_run(&[0; 0xE01], 10),
+            Err(ChipError::RomTooLarge(0xE01))
+        );
+        let _ = CPU::from_rom_and_run(&[0xFF; 0xE00], 1000);
+        let _ = CPU::from_rom_and_run(&[0x00; 0xE00], 1000);
+
+        let mut rng = SeededRng::new(1);
+        for _ in 0..100 {
+            let rom: Vec<u8> = (0..64).map(|_| rng.next_byte()).collect();
+            let _ = CPU::from_rom_and_run(&rom, 1000);
+        }
+        for opcode in (0..=0xFFFFu16).step_by(7) {
+            let _ = CPU::from_rom_and_run(&opcode.to_be_bytes(), 100);
+        }
+    }
+
+    #[test]
+    fn set_keys_drives_the_key_skips() {
+        // E09E: skip if key V0 is down, E1A1: skip if key V1 is up; E123 traps a missed skip
+        let rom = [0xE0, 0x9E, 0xE1, 0x23, 0xE1, 0xA1, 0xE1, 0x23, 0x00, 0x00];
+        let mut pressed = [false; 16];
+        pressed[5] = true;
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 6;
+        cpu.set_keys(pressed);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        cpu.load_rom(&rom).unwrap();
+        cpu.clear_keys();
+        assert_eq!(
+            cpu.run(),
+            Err(ChipError::UnknownOpcode {
+                opcode: 0xE123,
+                addr: 0x202
+            })
+        );
+        assert!(cpu.keys.is_empty());
+    }
+
+    #[test]
+    fn display_accessor_shows_a_drawn_sprite() {
+        let mut cpu = CPU::new();
+        // A300, D001: draw the 0b1010_0001 row at (0, 0)
+        cpu.load_rom(&[0xA3, 0x00, 0xD0, 0x01, 0x00, 0x00]).unwrap();
+        cpu.memory[0x300] = 0b1010_0001;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.display()[0][..8], [1, 0, 1, 0, 0, 0, 0, 1]);
+        assert!(cpu.pixel(0, 0) && !cpu.pixel(1, 0) && cpu.pixel(7, 0));
+        assert!(!cpu.pixel(0, 1));
+        // off screen in low resolution
+        assert!(!cpu.pixel(LORES_WIDTH, 0));
+    }
+
+    #[test]
+    fn reset_restores_the_power_on_state() {
+        let mut cpu = CPU::with_mode(Mode::SuperChip);
+        // the example program, with a few drawing and flag opcodes added before it halts
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..12].copy_from_slice(&[
+            0x21, 0x00, 0x21, 0x00, 0x00, 0xFF, 0xD0, 0x10, 0xF1, 0x75, 0x00, 0x00,
+        ]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        cpu.set_key(4, true);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[0], 45);
+
+        cpu.reset();
+        let fresh = CPU::with_mode(Mode::SuperChip);
+        assert_eq!(cpu.registers, fresh.registers);
+        assert_eq!(cpu.memory, fresh.memory);
+        assert_eq!(cpu.position_in_memory, fresh.position_in_memory);
+        assert_eq!(cpu.stack, fresh.stack);
+        assert_eq!(cpu.stack_pointer, fresh.stack_pointer);
+        assert_eq!(cpu.instruction_count(), 0);
+        assert_eq!(cpu.keys, fresh.keys);
+        assert_eq!(cpu.index, fresh.index);
+        assert_eq!(cpu.display(), fresh.display());
+        assert_eq!((cpu.width(), cpu.height()), (fresh.width(), fresh.height()));
+        assert_eq!(cpu.selected_planes(), fresh.selected_planes());
+        assert_eq!(cpu.rpl_flags, fresh.rpl_flags);
+        assert!(!cpu.take_display_dirty());
+        assert_eq!(cpu.mode(), Mode::SuperChip);
+    }
+
+    #[test]
+    fn on_beep_fires_on_edges_only() {
+        let edges = Rc::new(RefCell::new(Vec::new()));
+        let now = Rc::new(Cell::new(0));
+
+        let mut cpu = CPU::new();
+        let (recorded, clock) = (edges.clone(), now.clone());
+        cpu.set_on_beep(move |beeping| recorded.borrow_mut().push((clock.get(), beeping)));
+        // F018: sound timer = V0
+        cpu.load_rom(&[0xF0, 0x18, 0x00, 0x00]).unwrap();
+        cpu.registers[0] = 3;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.sound_timer(), 3);
+
+        for tick in 0..6 {
+            now.set(tick);
+            cpu.tick_timers();
+        }
+        assert_eq!(cpu.sound_timer(), 0);
+        assert_eq!(*edges.borrow(), [(0, true), (3, false)]);
+    }
+
+    #[test]
+    fn delay_timer_counts_down() {
+        let mut cpu = CPU::new();
+        // F015: delay timer = V0, F107: V1 = delay timer
+        cpu.load_rom(&[0xF0, 0x15, 0xF1, 0x07, 0x00, 0x00]).unwrap();
+        cpu.registers[0] = 2;
+        cpu.step().unwrap();
+        cpu.tick_timers();
+        assert_eq!(cpu.step(), Ok(HaltReason::Running));
+        assert_eq!(cpu.registers[1], 1);
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(cpu.delay_timer(), 0);
+    }
+
+    #[test]
+    fn run_random_program_is_reproducible() {
+        assert_eq!(
+            run_random_program(42, 64, 1000),
+            run_random_program(42, 64, 1000)
+        );
+        let mut checked = 0;
+        for seed in 0..200 {
+            if let Ok(state) = run_random_program(seed, 256, 1000) {
+                assert!(state.invariants_hold(), "seed {}", seed);
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn peripherals_see_stores_and_can_answer_loads() {
+        struct Constant(u8);
+        impl Peripheral for Constant {
+            fn on_write(&mut self, _addr: usize, value: u8) {
+                self.0 = value;
+            }
+
+            fn on_read(&mut self, _addr: usize) -> Option<u8> {
+                Some(self.0)
+            }
+        }
+
+        let mut cpu = CPU::new();
+        cpu.attach_peripheral(0xF00..0xF01, Constant(0x80));
+        // AF00, D001: draw the sprite the peripheral supplies
+        cpu.load_rom(&[0xAF, 0x00, 0xD0, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert!(cpu.pixel(0, 0) && !cpu.pixel(1, 0));
+        assert_eq!(cpu.memory[0xF00], 0);
+
+        // with it detached, F055 stores go straight to memory
+        cpu.detach_peripheral();
+        cpu.load_rom(&[0xAE, 0x00, 0xF0, 0x55, 0x00, 0x00]).unwrap();
+        cpu.registers[0] = 7;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.memory[0xE00], 7);
+    }
+
+    #[test]
+    fn coverage_lists_the_example_programs_opcodes() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        assert_eq!(
+            *cpu.opcode_coverage(),
+            HashSet::from([0x2100, 0x8014, 0x00EE, 0x0000])
+        );
+        cpu.reset();
+        assert!(cpu.opcode_coverage().is_empty());
+    }
+
+    #[test]
+    fn debug_shows_registers_and_display_shows_the_screen() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        let debug = format!("{:?}", cpu);
+        assert!(debug.contains("V0=0x2D"), "{}", debug);
+        assert!(
+            debug.contains("PC=0x006 I=0x000 SP=0 DT=0 ST=0 stack=[]"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains(" of 4096 bytes nonzero] }"), "{}", debug);
+
+        let text = cpu.to_string();
+        assert_eq!(text.lines().count(), LORES_HEIGHT);
+        assert!(text.lines().all(|line| line == ".".repeat(LORES_WIDTH)));
+    }
+
+    #[test]
+    fn save_states_round_trip_and_reject_garbage() {
+        let mut cpu = CPU::with_mode(Mode::XoChip);
+        // F015, 2208: call, then halt; at 0x208 F301 selects both planes, 8014, 00EE
+        cpu.load_rom(&[
+            0xF0, 0x15, 0x22, 0x08, 0x00, 0x00, 0x00, 0x00, 0xF3, 0x01, 0x80, 0x14, 0x00, 0xEE,
+        ])
+        .unwrap();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 5;
+        cpu.registers[3] = 3;
+        cpu.set_key(7, true);
+        // stop inside the subroutine, after F301
+        assert_eq!(cpu.run_bounded(3), Err(ChipError::StepLimitExceeded));
+        let saved = cpu.serialize();
+
+        let mut restored = CPU::deserialize(&saved).unwrap();
+        assert_eq!(restored.state(), cpu.state());
+        assert_eq!(restored.mode(), Mode::XoChip);
+        assert_eq!(restored.selected_planes(), 0b11);
+
+        // both carry on to the same place
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(restored.run(), Ok(HaltReason::Halted));
+        assert_eq!(restored.state(), cpu.state());
+        assert_eq!(restored.registers[0], 10);
+
+        let invalid = |reason| Err(ChipError::InvalidSaveState(reason));
+        let err = |bytes: &[u8]| CPU::deserialize(bytes).map(|cpu| cpu.state());
+        assert_eq!(err(b"NOPE"), invalid("bad magic"));
+        assert_eq!(err(SAVE_MAGIC), invalid("missing version"));
+
+        let mut wrong_version = saved.clone();
+        wrong_version[4] += 1;
+        assert_eq!(err(&wrong_version), invalid("unsupported version"));
+        assert_eq!(err(&saved[..saved.len() - 1]), invalid("truncated"));
+
+        let mut trailing = saved.clone();
+        trailing.push(0);
+        assert_eq!(err(&trailing), invalid("trailing bytes"));
+    }
+
+    #[test]
+    fn load_rom_at_places_the_rom_and_the_pc() {
+        let mut cpu = CPU::new();
+        cpu.load_rom_at(&[0x80, 0x14, 0x00, 0x00], 0x600).unwrap();
+        assert_eq!(cpu.memory[0x600..0x604], [0x80, 0x14, 0x00, 0x00]);
+        assert_eq!(cpu.state().pc, 0x600);
+        assert_eq!(cpu.memory[0x200], 0);
+
+        cpu.load_rom(&[0x00, 0xE0]).unwrap();
+        assert_eq!(cpu.state().pc, PROGRAM_START);
+
+        assert_eq!(cpu.load_rom_at(&[0; 0x10], 0xFF0), Ok(()));
+        assert_eq!(
+            cpu.load_rom_at(&[0; 0x11], 0xFF0),
+            Err(ChipError::RomTooLarge(0x11))
+        );
+        assert_eq!(cpu.load_rom_at(&[], 0x1001), Err(ChipError::RomTooLarge(0)));
+    }
+
+    #[test]
+    fn dump_registers_marks_the_flag() {
+        let mut cpu = CPU::new();
+        // 8014: V0 += V1, overflowing and setting VF
+        cpu.load_rom(&[0x80, 0x14, 0x00, 0x00]).unwrap();
+        cpu.registers[0] = 200;
+        cpu.registers[1] = 100;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        let dump = dump_registers(&cpu);
+        assert_eq!(dump.lines().count(), 16);
+        assert_eq!(dump.lines().next(), Some("V0 = 0x2C ( 44)"));
+        assert_eq!(dump.lines().last(), Some("VF = 0x01 (  1)  <- flag"));
+    }
+
+    #[test]
+    fn fx29_follows_the_relocated_font() {
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.memory[FONT_ADDRESS..FONT_ADDRESS + FONT.len()], FONT);
+        cpu.set_font_address(0x100).unwrap();
+        assert_eq!(cpu.font_address(), 0x100);
+        assert_eq!(cpu.memory[0x100..0x100 + FONT.len()], FONT);
+        assert!(cpu.memory[FONT_ADDRESS..FONT_ADDRESS + FONT.len()]
+            .iter()
+            .all(|&byte| byte == 0));
+
+        // F029: I = glyph for the digit in V0
+        cpu.load_rom(&[0xF0, 0x29, 0x00, 0x00]).unwrap();
+        cpu.registers[0] = 3;
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.index, 0x10F);
+        assert_eq!(cpu.memory[0x10F..0x114], [0xF0, 0x10, 0xF0, 0x10, 0xF0]);
+
+        cpu.reset();
+        assert_eq!(cpu.font_address(), 0x100);
+        assert_eq!(cpu.memory[0x100..0x100 + FONT.len()], FONT);
+    }
+
+    #[test]
+    fn encode_inverts_decode_for_every_opcode() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(encode(decode(opcode)), opcode, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn pc_overflow_policy() {
+        // 8014 in the last two bytes of memory
+        let mut cpu = CPU::new();
+        cpu.load_rom_at(&[0x80, 0x14], 0xFFE).unwrap();
+        assert_eq!(cpu.step(), Ok(HaltReason::Running));
+        assert_eq!(cpu.step(), Err(ChipError::PcOutOfBounds(0x1000)));
+
+        let mut cpu = CPU::new();
+        cpu.set_pc_overflow_policy(PcOverflowPolicy::WrapAround);
+        cpu.load_rom_at(&[0x80, 0x14], 0xFFE).unwrap();
+        assert_eq!(cpu.step(), Ok(HaltReason::Running));
+        assert_eq!(cpu.state().pc, 0);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+
+        // an opcode split across the end and the start of memory
+        let mut cpu = CPU::new();
+        cpu.load_rom_at(&[0x80], 0xFFF).unwrap();
+        cpu.memory[0x000] = 0x14;
+        cpu.registers[1] = 1;
+        assert_eq!(cpu.step(), Err(ChipError::PcOutOfBounds(0xFFF)));
+        cpu.set_pc_overflow_policy(PcOverflowPolicy::WrapAround);
+        assert_eq!(cpu.step(), Ok(HaltReason::Running));
+        assert_eq!((cpu.registers[0], cpu.state().pc), (1, 1));
+    }
+
+    #[test]
+    fn memory_access_is_bounds_checked() {
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.write_byte(0x300, 0xAB), Ok(()));
+        assert_eq!(cpu.read_byte(0x300), Ok(0xAB));
+        assert_eq!(cpu.read_byte(0x050), Ok(FONT[0]));
+        assert_eq!(
+            cpu.read_byte(0x1000),
+            Err(ChipError::AddressOutOfBounds(0x1000))
+        );
+        assert_eq!(
+            cpu.write_byte(0x1000, 1),
+            Err(ChipError::AddressOutOfBounds(0x1000))
+        );
+
+        assert_eq!(cpu.write_bytes(0xFFD, &[1, 2, 3]), Ok(()));
+        assert_eq!(cpu.read_byte(0xFFF), Ok(3));
+        // nothing is written unless all of it fits
+        assert_eq!(
+            cpu.write_bytes(0xFFE, &[9, 9, 9]),
+            Err(ChipError::AddressOutOfBounds(0x1000))
+        );
+        assert_eq!(cpu.read_byte(0xFFE), Ok(2));
+        assert_eq!(
+            cpu.write_bytes(usize::MAX, &[1]),
+            Err(ChipError::AddressOutOfBounds(usize::MAX))
+        );
+        assert_eq!(cpu.write_bytes(0x1000, &[]), Ok(()));
+    }
+
+    #[test]
+    fn registers_by_name() {
+        assert_eq!(Reg::V0 as usize, 0);
+        assert_eq!(Reg::VA as usize, 10);
+        assert_eq!(Reg::VF as usize, 15);
+        assert_eq!(FLAG, Reg::VF);
+
+        let mut cpu = CPU::new();
+        cpu.set_reg(Reg::V3, 0x42);
+        assert_eq!(cpu.reg(Reg::V3), 0x42);
+        assert_eq!(cpu.registers[3], 0x42);
+
+        // 8014 overflowing sets the flag
+        cpu.load_rom(&[0x80, 0x14, 0x00, 0x00]).unwrap();
+        cpu.set_reg(Reg::V0, 0xFF);
+        cpu.set_reg(Reg::V1, 0x01);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.reg(Reg::V0), cpu.reg(FLAG)), (0, 1));
+    }
+
+    #[test]
+    fn arithmetic_wraps_instead_of_panicking() {
+        let mut cpu = CPU::new();
+        // 7002: V0 += 2, F11E: I += V1, 8234: V2 += V3
+        cpu.load_rom(&[0x70, 0x02, 0xF1, 0x1E, 0x82, 0x34, 0x00, 0x00])
+            .unwrap();
+        cpu.set_reg(Reg::V0, 0xFF);
+        cpu.set_reg(Reg::V1, 0x02);
+        cpu.set_reg(Reg::V2, 0x80);
+        cpu.set_reg(Reg::V3, 0x81);
+        cpu.set_reg(FLAG, 7);
+        cpu.index = 0xFFFF;
+
+        cpu.step().unwrap();
+        // 7XNN leaves the flag alone
+        assert_eq!((cpu.reg(Reg::V0), cpu.reg(FLAG)), (0x01, 7));
+        cpu.step().unwrap();
+        assert_eq!(cpu.index, 0x0001);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.reg(Reg::V2), cpu.reg(FLAG)), (0x01, 1));
+    }
+
+    #[test]
+    fn on_frame_runs_once_per_frame() {
+        let frames = Rc::new(Cell::new(0));
+        let pcs = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = CPU::new();
+        let (count, seen) = (frames.clone(), pcs.clone());
+        cpu.set_on_frame(move |cpu| {
+            count.set(count.get() + 1);
+            seen.borrow_mut().push(cpu.state().pc);
+        });
+        // 1200: spin forever
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        for _ in 0..3 {
+            assert_eq!(cpu.run_frame(10), Ok(HaltReason::Running));
+        }
+        assert_eq!(frames.get(), 3);
+        assert_eq!(cpu.instruction_count(), 30);
+        assert_eq!(*pcs.borrow(), [0x200; 3]);
+
+        // a halt ends the frame early, but the callback still runs
+        cpu.load_rom(&[0x00, 0x00]).unwrap();
+        assert_eq!(cpu.run_frame(10), Ok(HaltReason::Halted));
+        assert_eq!(frames.get(), 4);
+    }
+
+    #[test]
+    fn export_ppm_scales_the_screen() {
+        // draw the top row of the "0" glyph: pixels 0 to 3 of row 0
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xA0, 0x50, 0xD0, 0x01, 0x00, 0x00]).unwrap();
+        cpu.run().unwrap();
+
+        let header = b"P6\n128 64\n255\n";
+        let ppm = cpu.export_ppm(2);
+        assert!(ppm.starts_with(header));
+        let pixels = &ppm[header.len()..];
+        assert_eq!(pixels.len(), 128 * 64 * 3);
+        assert_eq!(pixels[..8 * 3], [255; 24]);
+        assert_eq!(pixels[8 * 3..9 * 3], [0; 3]);
+        // the second row of the image is still the first row of the screen
+        assert_eq!(pixels[128 * 3..128 * 3 + 3], [255; 3]);
+
+        assert_eq!(cpu.export_ppm(0), b"P6\n0 0\n255\n");
+    }
+
+    #[test]
+    fn profile_counts_instruction_families() {
+        // 7001, then a chain of jumps back round until 300A sees V0 == 10
+        let rom = [
+            0x70, 0x01, 0x12, 0x04, 0x12, 0x06, 0x30, 0x0A, 0x12, 0x00, 0x00, 0x00,
+        ];
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert!(cpu.profile().is_empty());
+
+        cpu.load_rom(&rom).unwrap();
+        cpu.registers[0] = 0;
+        cpu.enable_profiling();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        let profile = cpu.profile();
+        assert_eq!(profile[&0x7], 10);
+        assert_eq!(profile[&0x3], 10);
+        assert_eq!(profile[&0x1], 29);
+        assert_eq!(profile[&0x0], 1);
+        let busiest = profile.iter().max_by_key(|(_, &count)| count);
+        assert_eq!(busiest, Some((&0x1, &29)));
+    }
+
+    #[test]
+    fn breakpoints_stop_run_before_the_instruction() {
+        // the example program, stopping on the second add of the first call
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        cpu.add_breakpoint(0x102);
+
+        assert_eq!(cpu.run(), Ok(HaltReason::Breakpoint(0x102)));
+        assert_eq!((cpu.state().pc, cpu.registers[0]), (0x102, 15));
+        // running again without stepping stays put
+        assert_eq!(cpu.run_bounded(10), Ok(HaltReason::Breakpoint(0x102)));
+        assert_eq!(cpu.registers[0], 15);
+
+        // the second call hits it again
+        assert_eq!(cpu.step(), Ok(HaltReason::Running));
+        assert_eq!(cpu.run(), Ok(HaltReason::Breakpoint(0x102)));
+        assert_eq!(cpu.registers[0], 35);
+
+        cpu.remove_breakpoint(0x102);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[0], 45);
+    }
+
+    #[test]
+    fn watchpoints_report_changes() {
+        // the example program: the first add is the first thing to touch V0
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        cpu.watch_register(0);
+
+        let changed = |old, new| {
+            Ok(HaltReason::Watchpoint {
+                location: Watched::Register(0),
+                old,
+                new,
+            })
+        };
+        assert_eq!(cpu.run(), changed(5, 15));
+        assert_eq!((cpu.state().pc, cpu.instruction_count()), (0x102, 2));
+        assert_eq!(cpu.run(), changed(15, 25));
+
+        cpu.clear_watchpoints();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[0], 45);
+
+        // F055 storing into watched memory
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x55, 0x00, 0x00])
+            .unwrap();
+        cpu.registers[0] = 7;
+        cpu.watch_memory(0x300);
+        cpu.watch_memory(0x1000);
+        assert_eq!(
+            cpu.run(),
+            Ok(HaltReason::Watchpoint {
+                location: Watched::Memory(0x300),
+                old: 0,
+                new: 7
+            })
+        );
+        // storing the same value again isn't a change
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+    }
+
+    #[test]
+    fn step_back_undoes_steps_up_to_the_history() {
+        let mut cpu = CPU::new();
+        // 7001 four times, then halt
+        cpu.load_rom(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(cpu.step_back(), Err(ChipError::NothingToRewind));
+        cpu.enable_rewind(2);
+
+        let mut states = vec![cpu.state()];
+        for _ in 0..3 {
+            cpu.step().unwrap();
+            states.push(cpu.state());
+        }
+        assert_eq!(cpu.registers[0], 3);
+
+        cpu.step_back().unwrap();
+        assert_eq!(cpu.state(), states[2]);
+        cpu.step_back().unwrap();
+        assert_eq!(cpu.state(), states[1]);
+        assert_eq!(cpu.registers[0], 1);
+        // only two steps were kept
+        assert_eq!(cpu.step_back(), Err(ChipError::NothingToRewind));
+
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[0], 4);
+    }
+
+    #[test]
+    fn state_diff_lists_what_the_example_changed() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        let before = cpu.state();
+        assert!(before.diff(&before).is_empty());
+
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        let diff = before.diff(&cpu.state());
+        assert_eq!(diff.registers, [(0, 5, 45)]);
+        assert_eq!(diff.pc, Some((0, 6)));
+        // the calls left their return addresses behind, but the stack is empty again
+        assert_eq!(diff.stack_pointer, None);
+        assert!(diff.memory.is_empty() && diff.index.is_none());
+    }
+
+    #[test]
+    fn diff_memory_compares_the_common_prefix() {
+        assert_eq!(
+            diff_memory(&[1, 2, 3, 4], &[1, 9, 3, 8, 5]),
+            [(1, 2, 9), (3, 4, 8)]
+        );
+        assert!(diff_memory(&[], &[1]).is_empty());
+    }
+
+    #[test]
+    fn unknown_opcode_errors_say_where() {
+        let mut cpu = CPU::new();
+        // three clears, then E123 at 0x206
+        cpu.load_rom(&[0x00, 0xE0, 0x00, 0xE0, 0x00, 0xE0, 0xE1, 0x23])
+            .unwrap();
+        let err = cpu.run().unwrap_err();
+        assert_eq!(
+            err,
+            ChipError::UnknownOpcode {
+                opcode: 0xE123,
+                addr: 0x206
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "unknown opcode 0xE123 at address 0x206 (high nibble E)"
+        );
+    }
+
+    #[test]
+    fn keypad_mapping_round_trips() {
+        for key in 0..16 {
+            assert_eq!(host_key_to_chip8(chip8_to_host_key(key)), Some(key));
+        }
+        for host in "1234qwerasdfzxcv".chars() {
+            assert_eq!(host_key_to_chip8(host).map(chip8_to_host_key), Some(host));
+        }
+        assert_eq!(host_key_to_chip8('q'), Some(0x4));
+        assert_eq!(host_key_to_chip8('Q'), Some(0x4));
+        assert_eq!(host_key_to_chip8('x'), Some(0x0));
+        assert_eq!(host_key_to_chip8('p'), None);
+        assert_eq!(chip8_to_host_key(0x1F), 'v');
+    }
+
+    #[test]
+    fn render_sprites_as_text() {
+        assert_eq!(
+            render_sprite(&FONT[..5]),
+            "####    \n#  #    \n#  #    \n#  #    \n####    \n"
+        );
+        assert_eq!(render_large_sprite(&[0xFF, 0x01]), "########       #\n");
+        // SUPER-CHIP's large digits are still a byte wide, just ten rows tall
+        assert!(render_sprite(&LARGE_FONT[..10]).starts_with("  ####  \n ###### \n"));
+        assert_eq!(render_sprite(&[]), "");
+    }
+
+    #[test]
+    fn beep_samples_is_a_square_wave() {
+        let samples = beep_samples(8000, Duration::from_millis(10), 1000.0);
+        assert_eq!(samples.len(), 80);
+        assert_eq!(samples[..8], [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+        assert!(samples.iter().all(|&s| s == 1.0 || s == -1.0));
+        // a whole number of cycles, half of it high
+        assert_eq!(samples.iter().filter(|&&s| s == 1.0).count(), 40);
+
+        let second = beep_samples(44100, Duration::from_secs(1), BEEP_FREQUENCY);
+        assert_eq!(second.len(), 44100);
+        assert!(beep_samples(44100, Duration::ZERO, BEEP_FREQUENCY).is_empty());
+    }
+
+    #[test]
+    fn step_over_runs_a_whole_subroutine() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+
+        // the first 2100 runs CALL, ADD, ADD, RET in one go
+        assert_eq!(cpu.step_over(), Ok(HaltReason::Running));
+        assert_eq!((cpu.state().pc, cpu.instruction_count()), (0x002, 4));
+        assert_eq!(cpu.registers[0], 25);
+
+        // a breakpoint inside the subroutine still stops it
+        cpu.add_breakpoint(0x102);
+        assert_eq!(cpu.step_over(), Ok(HaltReason::Breakpoint(0x102)));
+        cpu.remove_breakpoint(0x102);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.registers[0], 45);
+
+        // anything else is a single step
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x70, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.step_over(), Ok(HaltReason::Running));
+        assert_eq!(cpu.instruction_count(), 1);
+    }
+
+    #[test]
+    fn erased_pixels_fade_out() {
+        let mut cpu = CPU::new();
+        // A300, D001: draw one pixel, then D001 again to erase it, then spin
+        cpu.load_rom(&[0xA3, 0x00, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06])
+            .unwrap();
+        cpu.write_byte(0x300, 0x80).unwrap();
+        assert!(cpu.render_intensity().is_none());
+        cpu.enable_fade(100);
+
+        cpu.run_frame(2).unwrap();
+        assert_eq!(cpu.render_intensity().unwrap()[0][..2], [255, 0]);
+        cpu.run_frame(1).unwrap();
+        assert!(!cpu.pixel(0, 0));
+        assert_eq!(cpu.render_intensity().unwrap()[0][0], 155);
+        cpu.run_frame(1).unwrap();
+        assert_eq!(cpu.render_intensity().unwrap()[0][0], 55);
+        cpu.run_frame(1).unwrap();
+        assert_eq!(cpu.render_intensity().unwrap()[0][0], 0);
+
+        cpu.disable_fade();
+        assert!(cpu.render_intensity().is_none());
+    }
+
+    #[test]
+    fn step_verbose_returns_the_instruction() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+
+        assert_eq!(
+            cpu.step_verbose(),
+            Ok((Instruction::Call(0x100), HaltReason::Running))
+        );
+        assert_eq!(
+            cpu.step_verbose(),
+            Ok((Instruction::AddXY { x: 0, y: 1 }, HaltReason::Running))
+        );
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(
+            cpu.step_verbose(),
+            Ok((Instruction::Halt, HaltReason::Halted))
+        );
+    }
+
+    #[test]
+    fn run_at_hz_ticks_the_timers_in_real_time() {
+        let mut cpu = CPU::new();
+        // F015: delay timer = V0, then twenty 7101s and a halt
+        let mut rom = vec![0xF0, 0x15];
+        rom.extend([0x71, 0x01].repeat(20));
+        rom.extend([0x00, 0x00]);
+        cpu.load_rom(&rom).unwrap();
+        cpu.registers[0] = 255;
+
+        let start = Instant::now();
+        assert_eq!(cpu.run_at_hz(1000, 100), Ok(HaltReason::Halted));
+        assert!(start.elapsed() >= Duration::from_millis(21));
+        assert_eq!(cpu.registers[1], 20);
+        // at least the ticks due 10ms and 20ms in have happened
+        assert!(cpu.delay_timer() <= 253, "{}", cpu.delay_timer());
+    }
+
+    #[test]
+    fn backtrace_lists_the_callers() {
+        let mut cpu = CPU::new();
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 10;
+        cpu.memory[..6].copy_from_slice(&[0x21, 0x00, 0x21, 0x00, 0x00, 0x00]);
+        cpu.memory[0x100..0x106].copy_from_slice(&[0x80, 0x14, 0x80, 0x14, 0x00, 0xEE]);
+        assert!(cpu.backtrace().is_empty());
+
+        // step into the first call but not back out of it
+        assert_eq!(
+            cpu.step_verbose(),
+            Ok((Instruction::Call(0x100), HaltReason::Running))
+        );
+        assert_eq!(cpu.backtrace(), vec![0x002]);
+
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert!(cpu.backtrace().is_empty());
+    }
+
+    #[test]
+    fn fx1e_vf_follows_the_amiga_quirk() {
+        // AFFE: I = 0xFFE, F01E: I += V0 (V0 = 1, stays in range), F01E: I += V0 (past 0xFFF)
+        let rom = [0xAF, 0xFE, 0xF0, 0x1E, 0xF0, 0x1E, 0x00, 0x00];
+
+        let mut cpu = CPU::new();
+        cpu.set_quirks(Quirks {
+            fx1e_sets_vf: true,
+            ..Quirks::default()
+        });
+        cpu.set_reg(Reg::V0, 1);
+        cpu.set_reg(FLAG, 7);
+        cpu.load_rom(&rom).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.state().index, cpu.reg(FLAG)), (0xFFF, 0));
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.state().index, cpu.reg(FLAG)), (0x1000, 1));
+
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.quirks(), Quirks::default());
+        cpu.set_reg(Reg::V0, 1);
+        cpu.set_reg(FLAG, 7);
+        cpu.load_rom(&rom).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.state().index, cpu.reg(FLAG)), (0x1000, 7));
+    }
+
+    #[test]
+    fn display_wait_allows_one_draw_per_frame() {
+        // A050: I = the "0" glyph, then draws at x = V2, V0 and V1 (0, 8 and 16) and a halt
+        let rom = [0xA0, 0x50, 0xD2, 0x21, 0xD0, 0x21, 0xD1, 0x21, 0x00, 0x00];
+        let mut cpu = CPU::new();
+        cpu.set_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+        cpu.set_reg(Reg::V0, 8);
+        cpu.set_reg(Reg::V1, 16);
+        cpu.load_rom(&rom).unwrap();
+
+        assert_eq!(cpu.run_frame(100), Ok(HaltReason::Running));
+        assert!(cpu.pixel(0, 0) && !cpu.pixel(8, 0));
+        assert_eq!(cpu.run_frame(100), Ok(HaltReason::Running));
+        assert!(cpu.pixel(8, 0) && !cpu.pixel(16, 0));
+        assert_eq!(cpu.run_frame(100), Ok(HaltReason::Halted));
+        assert!(cpu.pixel(16, 0));
+
+        // without the quirk all three draws land in the first frame
+        let mut cpu = CPU::new();
+        cpu.set_reg(Reg::V0, 8);
+        cpu.set_reg(Reg::V1, 16);
+        cpu.load_rom(&rom).unwrap();
+        assert_eq!(cpu.run_frame(100), Ok(HaltReason::Halted));
+        assert!(cpu.pixel(0, 0) && cpu.pixel(8, 0) && cpu.pixel(16, 0));
+    }
+
+    #[test]
+    fn describe_address_finds_the_font() {
+        let cpu = CPU::new();
+        assert_eq!(cpu.describe_address(0x060), "font");
+        assert_eq!(cpu.describe_address(0x0A0), "large font");
+        assert_eq!(cpu.describe_address(0xFFF), "program and work RAM");
+
+        // the regions cover every address exactly once
+        let map = memory_map();
+        assert_eq!(map.first().unwrap().0.start, 0);
+        assert_eq!(map.last().unwrap().0.end, 0x1000);
+        assert!(map.windows(2).all(|w| w[0].0.end == w[1].0.start));
+    }
+
+    #[test]
+    fn get_register_masks_the_index() {
+        let mut cpu = CPU::new();
+        cpu.set_reg(Reg::VF, 0x99);
+        assert_eq!(cpu.get_register(0x1F), 0x99);
+        assert_eq!(cpu.get_register(0xFF), 0x99);
+
+        cpu.set_register(0x10, 1);
+        assert_eq!(cpu.reg(Reg::V0), 1);
+    }
+
+    #[test]
+    fn upscale_by_two_makes_2x2_blocks() {
+        let mut display = [[false; LORES_WIDTH]; LORES_HEIGHT];
+        display[3][7] = true;
+
+        let scaled = upscale(&display, 2);
+        assert_eq!((scaled.len(), scaled[0].len()), (64, 128));
+        for (y, row) in scaled.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                assert_eq!(pixel, display[y / 2][x / 2]);
+            }
+        }
+
+        assert_eq!(
+            upscale(&display, 1),
+            display.map(|row| row.to_vec()).to_vec()
+        );
+    }
+
+    #[test]
+    fn plane_select_is_only_legal_on_xo_chip() {
+        assert!(!is_legal_opcode(0xF101, Mode::Chip8));
+        assert!(is_legal_opcode(0xF101, Mode::XoChip));
+
+        // every CHIP-8 opcode is legal in the later variants too
+        for opcode in [0x00E0, 0x1234, 0x6A42, 0xD015, 0xF065] {
+            assert!(is_legal_opcode(opcode, Mode::Chip8));
+            assert!(is_legal_opcode(opcode, Mode::SuperChip));
+            assert!(is_legal_opcode(opcode, Mode::XoChip));
+        }
+    }
+
+    // a screen in render_to_string's format with `rows` drawn at the top left
+    fn expected_screen(rows: &[&str]) -> String {
+        (0..LORES_HEIGHT)
+            .map(|y| {
+                let drawn = rows.get(y).copied().unwrap_or("");
+                format!("{:.<width$}\n", drawn, width = LORES_WIDTH)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn run_test_rom_checks_the_screen() {
+        // 3000: skip the next instruction if V0 == 0, 0000: halt (skipped), A05A: I = the
+        // "2" glyph, D005: draw it at (0, 0), 0000: halt. A 2 means the skip worked.
+        let rom = [0x30, 0x00, 0x00, 0x00, 0xA0, 0x5A, 0xD0, 0x05, 0x00, 0x00];
+        let two = ["####", "...#", "####", "#...", "####"];
+        assert!(run_test_rom(&rom, &expected_screen(&two)));
+        assert!(!run_test_rom(&rom, &expected_screen(&[])));
+
+        // a ROM too big to load fails too
+        assert!(!run_test_rom(&[0; 0x1000], &expected_screen(&[])));
+    }
+
+    #[test]
+    fn sub_with_flag_sets_not_borrow() {
+        assert_eq!(sub_with_flag(5, 3), (2, 1));
+        assert_eq!(sub_with_flag(3, 5), (254, 0));
+        assert_eq!(sub_with_flag(0, 255), (1, 0));
+    }
+
+    #[test]
+    fn subtract_opcodes_agree() {
+        // 8015: V0 = V0 - V1 and 8327: V3 = V2 - V3 compute the same thing
+        let mut cpu = CPU::new();
+        cpu.set_reg(Reg::V0, 10);
+        cpu.set_reg(Reg::V1, 20);
+        cpu.set_reg(Reg::V2, 10);
+        cpu.set_reg(Reg::V3, 20);
+        cpu.load_rom(&[0x80, 0x15, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        let subtracted = (cpu.reg(Reg::V0), cpu.reg(FLAG));
+
+        cpu.load_rom(&[0x83, 0x27, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.reg(Reg::V3), cpu.reg(FLAG)), subtracted);
+        assert_eq!(subtracted, (246, 0));
+    }
+
+    #[test]
+    fn shift_flags_hold_the_bit_shifted_out() {
+        assert_eq!(shl_with_flag(0x80), (0x00, 1));
+        assert_eq!(shl_with_flag(0x7F), (0xFE, 0));
+        assert_eq!(shr_with_flag(0x01), (0x00, 1));
+        assert_eq!(shr_with_flag(0xFE), (0x7F, 0));
+    }
+
+    #[test]
+    fn shift_into_vf_keeps_the_flag() {
+        // 8F06: VF >>= 1, so the flag overwrites the shifted value
+        let mut cpu = CPU::new();
+        cpu.set_reg(FLAG, 0x02);
+        cpu.load_rom(&[0x8F, 0x06, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!(cpu.reg(FLAG), 0);
+    }
+
+    #[test]
+    fn load_immediate() {
+        // 6A42: VA = 0x42, 6AFF: VA = 0xFF, neither touching VF
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x6A, 0x42, 0x6A, 0xFF, 0x00, 0x00]).unwrap();
+        cpu.set_reg(FLAG, 7);
+        cpu.step().unwrap();
+        assert_eq!(cpu.reg(Reg::VA), 0x42);
+        assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+        assert_eq!((cpu.reg(Reg::VA), cpu.reg(FLAG)), (0xFF, 7));
+    }
+
+    #[test]
+    fn register_to_register_ops() {
+        // 8010: V0 = V1, 8021: V0 |= V2, 8032: V0 &= V3, 8043: V0 ^= V4
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x80, 0x10, 0x80, 0x21, 0x80, 0x32, 0x80, 0x43, 0x00, 0x00])
+            .unwrap();
+        cpu.registers[1..5].copy_from_slice(&[0x0C, 0x30, 0x3C, 0xFF]);
+        cpu.set_reg(FLAG, 7);
+
+        let mut results = Vec::new();
+        while cpu.step() == Ok(HaltReason::Running) {
+            results.push(cpu.registers[0]);
+        }
+        assert_eq!(results, vec![0x0C, 0x3C, 0x3C, 0xC3]);
+        assert_eq!(cpu.reg(FLAG), 7);
+    }
+
+    #[test]
+    fn jump_plus_v0() {
+        // B300 with V0 = 4: jump to 0x304
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xB3, 0x00]).unwrap();
+        cpu.registers[0] = 4;
+        cpu.step().unwrap();
+        assert_eq!(cpu.state().pc, 0x304);
+
+        // BFFF with V0 = 0xFF goes past the end of memory
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xBF, 0xFF]).unwrap();
+        cpu.registers[0] = 0xFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(ChipError::PcOutOfBounds(0x10FE)));
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xBF, 0xFF]).unwrap();
+        cpu.set_pc_overflow_policy(PcOverflowPolicy::WrapAround);
+        cpu.registers[0] = 0xFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.state().pc, 0x0FE);
+    }
+
+    #[test]
+    fn bcd_and_register_loads() {
+        // A300: I = 0x300, F033: BCD of V0 at I, F265: load V0..=V2 from I
+        let rom = [0xA3, 0x00, 0xF0, 0x33, 0xF2, 0x65, 0x00, 0x00];
+        for (value, digits) in [
+            (0, [0, 0, 0]),
+            (7, [0, 0, 7]),
+            (42, [0, 4, 2]),
+            (255, [2, 5, 5]),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.load_rom(&rom).unwrap();
+            cpu.registers[0] = value;
+            cpu.registers[3] = 0x99;
+            assert_eq!(cpu.run(), Ok(HaltReason::Halted));
+            assert_eq!(cpu.memory[0x300..0x303], digits);
+            assert_eq!(cpu.registers[..4], [digits[0], digits[1], digits[2], 0x99]);
+            assert_eq!(cpu.state().index, 0x300);
+        }
+    }
+
+    #[test]
+    fn test_roms_match_their_expected_screens() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+        let mut ran = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "ch8") {
+                continue;
+            }
+            let rom = fs::read(&path).unwrap();
+            let expected = fs::read_to_string(path.with_extension("txt")).unwrap();
+            assert!(run_test_rom(&rom, &expected), "{}", path.display());
+            ran += 1;
+        }
+        assert!(ran > 0, "no test ROMs in {}", dir.display());
+    }
+
+    #[test]
+    fn font_address_must_fit() {
+        let mut cpu = CPU::new();
+        let before = cpu.state();
+        for addr in [0x1000 - FONT.len() + 1, 0x1000, usize::MAX] {
+            assert_eq!(
+                cpu.set_font_address(addr),
+                Err(ChipError::AddressOutOfBounds(addr))
+            );
+        }
+        assert_eq!(cpu.state(), before);
+        assert_eq!(cpu.font_address(), FONT_ADDRESS);
+
+        let last = 0x1000 - FONT.len();
+        assert_eq!(cpu.set_font_address(last), Ok(()));
+        assert_eq!(cpu.memory[last..], FONT);
+    }
+
+    #[test]
+    fn save_states_keep_a_pc_past_the_end() {
+        // 3000: skip from the last instruction, leaving the PC at 0x1002
+        let mut cpu = CPU::new();
+        cpu.load_rom_at(&[0x30, 0x00], 0xFFE).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.state().pc, 0x1002);
+
+        let mut restored = CPU::deserialize(&cpu.serialize()).unwrap();
+        assert_eq!(restored.state(), cpu.state());
+        assert_eq!(restored.step(), Err(ChipError::PcOutOfBounds(0x1002)));
+
+        // BFFF with V0 = 0xFF gets as far as the PC can go
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xBF, 0xFF]).unwrap();
+        cpu.registers[0] = 0xFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.state().pc, MAX_PC);
+        assert_eq!(
+            CPU::deserialize(&cpu.serialize()).unwrap().state(),
+            cpu.state()
+        );
+
+        cpu.restore(&CpuState {
+            pc: MAX_PC + 1,
+            ..cpu.state()
+        });
+        assert_eq!(
+            CPU::deserialize(&cpu.serialize()).map(|cpu| cpu.state()),
+            Err(ChipError::InvalidSaveState(
+                "stack pointer or PC out of range"
+            ))
+        );
+    }
 }
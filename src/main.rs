@@ -1,8 +1,10 @@
 use crate::memory::printing_virtual_addresses;
 
+pub mod asm;
 pub mod bits;
 pub mod cpu;
 pub mod memory;
+pub mod util;
 
 fn main() {
     printing_virtual_addresses();
@@ -1,15 +1,23 @@
-/// Hierarchy of kinds of pointer (from simpler to more sophisticated):
-/// 1. A MEMORY ADDRESS, or just ADDRESS, is a number of size usize (typically 64 bits)
-///    that is interpreted as referring to a single byte in memory.
-/// 2. A RAW POINTER, or just POINTER, is a memory address interpreted as pointing to a
-///    value of some type.
-/// 3. A REFERENCE is a raw pointer (or in the case of dynamically sized types, a pointer
-///    and integer with extra guarantees) with the following advantages over raw pointers:
-///    - references always point to valid data (this is what the borrow checker guarantees).
-///    - references are correctly aligned to multiples of usize (by applying padding).
-///    - references provide these guarantees to dynamically sized types as well.
-
-use std::mem::size_of;
+//! Hierarchy of kinds of pointer (from simpler to more sophisticated):
+//! 1. A MEMORY ADDRESS, or just ADDRESS, is a number of size usize (typically 64 bits)
+//!    that is interpreted as referring to a single byte in memory.
+//! 2. A RAW POINTER, or just POINTER, is a memory address interpreted as pointing to a
+//!    value of some type.
+//! 3. A REFERENCE is a raw pointer (or in the case of dynamically sized types, a pointer
+//!    and integer with extra guarantees) with the following advantages over raw pointers:
+//!    - references always point to valid data (this is what the borrow checker guarantees).
+//!    - references are correctly aligned to multiples of usize (by applying padding).
+//!    - references provide these guarantees to dynamically sized types as well.
+
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::mem::{align_of, offset_of, size_of};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 static B: [u8; 10] = [99, 97, 114, 114, 121, 116, 111, 119, 101, 108];
 static C: [u8; 11] = [116, 194, 97, 110, 107, 115, 102, 105, 115, 104, 0];
@@ -51,6 +59,49 @@ pub fn references_and_memory() {
     println!("  value:    {:?}", C);
 }
 
+/// HEX DUMPS
+/// The raw view of a region of memory: each row starts with the address of its first byte, followed by the
+/// next 16 bytes in hex (in pairs, as xxd prints them) and then the same bytes as ASCII, with a '.' standing
+/// in for anything that isn't a printable character. Reading B and C this way shows that both are just
+/// bytes; it is only the ASCII gutter that makes them look like text. `base_addr` is the address the first
+/// byte of `data` should be labelled with, e.g. 0x200 for a CHIP-8 program.
+pub fn hexdump(data: &[u8], base_addr: usize) -> String {
+    let mut out = String::new();
+    for (row, bytes) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes
+            .chunks(2)
+            .map(|pair| pair.iter().map(|byte| format!("{:02x}", byte)).collect())
+            .collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}: {:<39}  {}\n",
+            base_addr + row * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+pub fn hexdump_demo() {
+    print!("{}", hexdump(&B, 0));
+    print!("{}", hexdump(&C, 0));
+
+    let mut data = *b"CHIP-8\0\x01\x02\xffhexdump!!!";
+    data[19] = b'\n';
+    assert_eq!(
+        hexdump(&data, 0x200),
+        "00000200: 4348 4950 2d38 0001 02ff 6865 7864 756d  CHIP-8....hexdum\n\
+         00000210: 7021 210a                                p!!.\n"
+    );
+}
+
 /// RAW POINTERS
 /// The difference between immutable raw pointers (*const T) and mutable ones (*mut T)
 /// is really just for the developer (each can easily be cast to the other). Also, rust
@@ -70,11 +121,335 @@ pub fn raw_pointers() {
     let a_ptr = &a as *const i64;
 
     // turn a raw pointer into an address
-    let a_addr: usize = unsafe { std::mem::transmute(a_ptr) };
+    let a_addr = a_ptr as usize;
 
     println!("a: {} ({:p}...0x{:x})", a, a_ptr, a_addr + 7);
 }
 
+/// TAGGED POINTERS
+/// Because every value of type T sits at an address that is a multiple of align_of::<T>(), the lowest
+/// align_of::<T>().trailing_zeros() bits of a pointer to T are always zero. Those bits carry no information,
+/// so they can be borrowed to store a small TAG alongside the pointer, as long as they are masked off again
+/// before the pointer is dereferenced. A *mut u64, for example, has 3 free bits, enough for a tag of 0 to 7.
+pub struct TaggedPtr<T> {
+    ptr: *mut T,
+}
+
+impl<T> TaggedPtr<T> {
+    pub const TAG_BITS: u32 = align_of::<T>().trailing_zeros();
+    const TAG_MASK: usize = (1 << Self::TAG_BITS) - 1;
+
+    pub fn new(ptr: *mut T, tag: usize) -> TaggedPtr<T> {
+        debug_assert!(
+            ptr.addr() & Self::TAG_MASK == 0,
+            "pointer is not aligned to {}",
+            align_of::<T>()
+        );
+
+        let mut tagged = TaggedPtr { ptr };
+        tagged.set_tag(tag);
+        tagged
+    }
+
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.map_addr(|addr| addr & !Self::TAG_MASK)
+    }
+
+    pub fn tag(&self) -> usize {
+        self.ptr.addr() & Self::TAG_MASK
+    }
+
+    pub fn set_tag(&mut self, tag: usize) {
+        debug_assert!(
+            tag <= Self::TAG_MASK,
+            "tag {} does not fit in {} bits",
+            tag,
+            Self::TAG_BITS
+        );
+
+        self.ptr = self.ptr().map_addr(|addr| addr | (tag & Self::TAG_MASK));
+    }
+}
+
+/// SMART POINTERS
+/// A smart pointer wraps a raw pointer and uses the type system to give it the guarantees of a reference.
+/// MyBox<T> is a minimal Box<T>: it asks the allocator for a block of heap memory laid out for a T, holds
+/// the address as a NonNull<T> (a *mut T that is promised never to be null), hands out references to the
+/// value through Deref/DerefMut, and gives the memory back to the allocator when it is dropped. All of the
+/// unsafe code lives in new and drop, so users of MyBox never have to touch a raw pointer themselves.
+pub struct MyBox<T> {
+    ptr: NonNull<T>,
+}
+
+impl<T> MyBox<T> {
+    pub fn new(value: T) -> MyBox<T> {
+        let layout = Layout::new::<T>();
+
+        // zero-sized types need no memory, and the allocator must not be asked for zero bytes
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            // SAFETY: the layout has a non-zero size.
+            let raw = unsafe { alloc(layout) } as *mut T;
+            NonNull::new(raw).unwrap_or_else(|| handle_alloc_error(layout))
+        };
+
+        // SAFETY: `ptr` is non-null, aligned for T, and points to memory nothing else is using.
+        unsafe { ptr.as_ptr().write(value) };
+
+        MyBox { ptr }
+    }
+}
+
+impl<T> Deref for MyBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: `ptr` points to a value initialised in new, and &self stops it being mutated for 'self.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for MyBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as for deref, and &mut self guarantees this is the only reference to the value.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for MyBox<T> {
+    fn drop(&mut self) {
+        let layout = Layout::new::<T>();
+
+        // SAFETY: the value is initialised and is never accessed again after being dropped here, and
+        // the memory was allocated in new with this same layout.
+        unsafe {
+            std::ptr::drop_in_place(self.ptr.as_ptr());
+            if layout.size() != 0 {
+                dealloc(self.ptr.as_ptr() as *mut u8, layout);
+            }
+        }
+    }
+}
+
+pub fn my_box_demo() {
+    let mut a = MyBox::new(42);
+    println!("a: {} at {:p}", *a, &*a as *const i32);
+
+    *a += 1;
+    assert_eq!(*a, 43);
+    println!("a: {} after incrementing through DerefMut", *a);
+}
+
+/// SHARED OWNERSHIP
+/// Box has exactly one owner. Rc<T> (and its thread-safe counterpart Arc<T>) allow several owners by storing
+/// a STRONG COUNT next to the value on the heap: cloning an Rc copies the pointer and increments the count,
+/// dropping one decrements it, and the value is only freed when the count reaches zero. A Weak pointer
+/// refers to the same allocation without owning it, so it doesn't contribute to the strong count and has to
+/// be upgraded (which fails once the value is gone) before it can be used.
+pub fn rc_refcount_demo() -> Vec<usize> {
+    let rc = Rc::new(String::from("shared"));
+    let mut counts = vec![Rc::strong_count(&rc)];
+
+    let mut clones = Vec::new();
+    for _ in 0..2 {
+        clones.push(Rc::clone(&rc));
+        counts.push(Rc::strong_count(&rc));
+    }
+
+    let weak = Rc::downgrade(&rc);
+    assert_eq!(Rc::strong_count(&rc), clones.len() + 1);
+    assert_eq!(Rc::weak_count(&rc), 1);
+
+    while clones.pop().is_some() {
+        counts.push(Rc::strong_count(&rc));
+    }
+
+    std::mem::drop(rc);
+    assert!(weak.upgrade().is_none());
+
+    println!("strong counts: {:?}", counts);
+    counts
+}
+
+/// ALLOCATORS
+/// Every Box::new, Vec::push past capacity, and String::from goes through the GLOBAL ALLOCATOR, which by
+/// default is the system allocator (malloc/free on unix). Any type implementing GlobalAlloc can be swapped in
+/// with #[global_allocator]. CountingAllocator forwards every request to the system allocator, keeping a tally
+/// of the bytes handed out and given back, which makes heap traffic that is normally invisible observable.
+#[cfg(feature = "trace_alloc")]
+pub use counting_allocator::{allocated_bytes, freed_bytes, live_bytes, CountingAllocator};
+
+#[cfg(feature = "trace_alloc")]
+mod counting_allocator {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub struct CountingAllocator;
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    #[global_allocator]
+    static COUNTING_ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                FREED.fetch_add(layout.size(), Ordering::Relaxed);
+                ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            }
+            new_ptr
+        }
+    }
+
+    // total bytes ever allocated
+    pub fn allocated_bytes() -> usize {
+        ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    // total bytes ever freed
+    pub fn freed_bytes() -> usize {
+        FREED.load(Ordering::Relaxed)
+    }
+
+    // bytes currently allocated and not yet freed
+    pub fn live_bytes() -> usize {
+        allocated_bytes().saturating_sub(freed_bytes())
+    }
+}
+
+#[cfg(feature = "trace_alloc")]
+pub fn allocation_demo() {
+    let before = live_bytes();
+    let v: Vec<u8> = Vec::with_capacity(1024);
+    let b = Box::new([0u64; 16]);
+    let during = live_bytes();
+
+    println!("live bytes before:  {}", before);
+    println!(
+        "live bytes during:  {} (Vec<u8> of 1024 + Box<[u64; 16]>)",
+        during
+    );
+    std::mem::drop(v);
+    std::mem::drop(b);
+    println!("live bytes after:   {}", live_bytes());
+}
+
+/// A singly-linked list built directly on raw pointers. Each node is allocated with Box::into_raw and
+/// freed with Box::from_raw, so the Box invariant applies: every node pointer is turned back into a Box
+/// exactly once, either when it is popped or when the list is dropped.
+///
+/// Ownership: the list owns every node reachable from `head`, and each node's `next` pointer is the only
+/// pointer to the node after it, so no node is ever reachable from two places. Aliasing: a &RawList only
+/// ever produces shared references into the nodes (through Iter), and mutation needs a &mut RawList, so the
+/// borrow checker still rules out mutating a node while an iterator is reading it, even though it can't see
+/// through the raw pointers themselves.
+struct Node<T> {
+    value: T,
+    next: *mut Node<T>,
+}
+
+pub struct RawList<T> {
+    head: *mut Node<T>,
+    len: usize,
+}
+
+impl<T> RawList<T> {
+    pub fn new() -> RawList<T> {
+        RawList {
+            head: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value,
+            next: self.head,
+        }));
+
+        self.head = node;
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+
+        // SAFETY: a non-null head came from Box::into_raw in push_front and is owned only by this
+        // list, and it is unlinked below so it can never be reclaimed a second time.
+        let node = unsafe { Box::from_raw(self.head) };
+        self.head = node.next;
+        self.len -= 1;
+
+        Some(node.value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head,
+            _list: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for RawList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for RawList<T> {
+    fn drop(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
+pub struct Iter<'a, T> {
+    next: *const Node<T>,
+    _list: PhantomData<&'a RawList<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.next.is_null() {
+            return None;
+        }
+
+        // SAFETY: nodes stay alive and unmodified for as long as the list is borrowed for 'a.
+        let node = unsafe { &*self.next };
+        self.next = node.next;
+
+        Some(&node.value)
+    }
+}
+
 /// The STACK is a region of program memory organised into a LIFO stack data structure consisting of STACK
 /// FRAMES. A new stack frame is created and pushed onto the bottom of the stack every time a function is
 /// called (it's pushed onto the bottom instead of the top because the stack grows down in memory). As the
@@ -107,7 +482,6 @@ pub fn raw_pointers() {
 /// not always next to each other, and so won't be cached as effectively. Accessing data on the heap also means
 /// dereferencing a pointer, which involves a page table lookup and a trip to main memory (a page table maps virtual
 /// memory addresses to real memory addresses).
-
 pub fn stack_and_heap() {
     // Box<T> is a smart pointer that will store T on the heap instead of the stack.
     let a = Box::new(1);
@@ -123,6 +497,275 @@ pub fn stack_and_heap() {
     println!("{} {}", result1, result2);
 }
 
+/// When a value goes out of scope its Drop implementation runs, and for a Box that includes freeing the
+/// heap memory. Values are dropped in the reverse order they were declared, since later values may borrow
+/// from earlier ones, and a value moved into std::mem::drop is dropped right there instead. The fields of a
+/// struct go the other way, in the order they are declared, once the struct's own Drop (if any) has run.
+/// DropLogger makes that order visible by recording its name in a shared log as it is dropped.
+pub struct DropLogger {
+    name: String,
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl DropLogger {
+    pub fn new(name: &str, log: &Rc<RefCell<Vec<String>>>) -> DropLogger {
+        DropLogger {
+            name: name.to_string(),
+            log: Rc::clone(log),
+        }
+    }
+}
+
+impl Drop for DropLogger {
+    fn drop(&mut self) {
+        self.log.borrow_mut().push(self.name.clone());
+    }
+}
+
+pub fn drop_order_demo() -> Vec<String> {
+    struct Pair {
+        _first: DropLogger,
+        _second: DropLogger,
+    }
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    {
+        let _a = DropLogger::new("stack a", &log);
+        let b = Box::new(DropLogger::new("boxed b", &log));
+        let _c = DropLogger::new("stack c", &log);
+        let _d = Box::new(DropLogger::new("boxed d", &log));
+        let _e = Pair {
+            _first: DropLogger::new("pair first", &log),
+            _second: DropLogger::new("pair second", &log),
+        };
+
+        std::mem::drop(b);
+        log.borrow_mut().push("end of scope".to_string());
+    }
+
+    let order = log.borrow().clone();
+    println!("{}", order.join(" -> "));
+    assert_eq!(
+        order,
+        [
+            "boxed b",
+            "end of scope",
+            "pair first",
+            "pair second",
+            "boxed d",
+            "stack c",
+            "stack a",
+        ]
+    );
+    order
+}
+
+/// The claim above that the stack grows down can be checked at runtime: a function called from inside
+/// another gets a newer frame, so its locals live at lower addresses than the caller's if the stack grows
+/// down. The callee must not be inlined, otherwise both locals would end up in the same frame.
+#[inline(never)]
+fn address_of_callee_local() -> usize {
+    let callee_local = 0u8;
+    std::hint::black_box(&callee_local) as *const u8 as usize
+}
+
+pub fn stack_grows_down() -> bool {
+    let caller_local = 0u8;
+    let caller_addr = std::hint::black_box(&caller_local) as *const u8 as usize;
+    let callee_addr = address_of_callee_local();
+
+    callee_addr < caller_addr
+}
+
+/// Where a value lives can be guessed from its address alone, because each region of program memory
+/// occupies its own range of virtual addresses: the stack near the top of the address space, static data
+/// alongside the program's code, and the heap somewhere in between. Comparing an address against a known
+/// stack local, a known static, and a fresh heap allocation, and picking whichever is closest, is a rough
+/// but telling heuristic.
+///
+/// It is only rough because the heap isn't one range. The allocator hands out memory from several arenas (one
+/// per thread, roughly, plus the main one just past the statics), and a thread's cache of freed blocks can
+/// return a block from any of them, so a fresh allocation can land a long way from the value being checked.
+/// On Linux there's no need to guess: /proc/self/maps lists every mapping in the address space, so the
+/// mapping holding the address says where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    Stack,
+    Heap,
+    Static,
+    Unknown,
+}
+
+// addresses further than this from every marker aren't attributed to any region
+const MAX_REGION_DISTANCE: usize = 1 << 30;
+
+pub fn region_of<T>(value_ref: &T) -> MemoryRegion {
+    let addr = value_ref as *const T as usize;
+
+    let stack_marker = 0u8;
+    let stack_addr = std::hint::black_box(&stack_marker) as *const u8 as usize;
+
+    #[cfg(target_os = "linux")]
+    if let Some(region) = region_from_memory_map(addr, stack_addr) {
+        return region;
+    }
+
+    // Without black_box the optimizer sees that the Box never escapes, and is free to drop the allocation
+    // and give the marker a stack slot instead.
+    let heap_marker = std::hint::black_box(Box::new(0u8));
+    let markers = [
+        (MemoryRegion::Stack, stack_addr),
+        (MemoryRegion::Heap, &*heap_marker as *const u8 as usize),
+        (MemoryRegion::Static, &GLOBAL as *const i32 as usize),
+    ];
+
+    let (region, distance) = markers
+        .iter()
+        .map(|&(region, marker)| (region, addr.abs_diff(marker)))
+        .min_by_key(|&(_, distance)| distance)
+        .unwrap();
+
+    if distance > MAX_REGION_DISTANCE {
+        MemoryRegion::Unknown
+    } else {
+        region
+    }
+}
+
+/// Each line of /proc/self/maps is one mapping: its address range, permissions, offset, device, inode and,
+/// for a file, its path. The stack is labelled [stack], though other threads' stacks are unlabelled, so the
+/// mapping holding the local `stack_addr` points to counts too. The heap is [heap] or any other anonymous
+/// mapping, since allocators mmap their extra arenas and large blocks. Statics live in the mappings of the
+/// executable itself, apart from the zero-initialised ones, which get an anonymous mapping straight after it.
+#[cfg(target_os = "linux")]
+fn region_from_memory_map(addr: usize, stack_addr: usize) -> Option<MemoryRegion> {
+    let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+    let mut previous: Option<(usize, &str)> = None;
+
+    for line in maps.lines() {
+        let mut fields = line.split_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let (start, end) = (
+            usize::from_str_radix(start, 16).ok()?,
+            usize::from_str_radix(end, 16).ok()?,
+        );
+        let path = fields.nth(4).unwrap_or("");
+
+        if (start..end).contains(&addr) {
+            let follows_a_file = matches!(previous, Some((prev_end, prev_path))
+                if prev_end == start && prev_path.starts_with('/'));
+            return Some(match path {
+                "[stack]" => MemoryRegion::Stack,
+                _ if (start..end).contains(&stack_addr) => MemoryRegion::Stack,
+                "[heap]" => MemoryRegion::Heap,
+                "" if follows_a_file => MemoryRegion::Static,
+                "" => MemoryRegion::Heap,
+                _ if path.starts_with('/') => MemoryRegion::Static,
+                _ => MemoryRegion::Unknown,
+            });
+        }
+        previous = Some((end, path));
+    }
+
+    Some(MemoryRegion::Unknown)
+}
+
+pub fn where_does_it_live() {
+    let local = 1;
+    // black_box keeps the allocation: a Box that never escapes can otherwise be turned into a stack slot
+    let boxed = std::hint::black_box(Box::new(2));
+
+    let local_addr = &local as *const i32 as usize;
+    let boxed_addr = &*boxed as *const i32 as usize;
+
+    println!("local:  {:#x} {:?}", local_addr, region_of(&local));
+    println!("boxed:  {:#x} {:?}", boxed_addr, region_of(&*boxed));
+    println!(
+        "GLOBAL: {:#x} {:?}",
+        &GLOBAL as *const i32 as usize,
+        region_of(&GLOBAL)
+    );
+    println!(
+        "local and boxed are {} bytes apart",
+        local_addr.abs_diff(boxed_addr)
+    );
+
+    // the pointee of a Box is nowhere near the function's own stack frame
+    assert_eq!(region_of(&local), MemoryRegion::Stack);
+    assert_eq!(region_of(&*boxed), MemoryRegion::Heap);
+    assert!(local_addr.abs_diff(boxed_addr) > size_of::<i32>() * 1024);
+}
+
+/// The claim that contiguous layout means fewer cache misses can be measured. An n x n matrix stored
+/// row-major in a flat Vec has each row laid out contiguously, so walking it row by row reads memory in
+/// order and every cache line fetched gets fully used. Walking it column by column jumps n elements between
+/// reads, touching a different cache line almost every time. Both traversals do exactly the same arithmetic,
+/// so any difference in time comes from the memory accesses.
+pub fn traversal_timing(n: usize) -> (Duration, Duration) {
+    let matrix: Vec<u64> = (0..(n * n) as u64).collect();
+
+    let start = Instant::now();
+    let mut row_major_sum = 0u64;
+    for row in 0..n {
+        for col in 0..n {
+            row_major_sum = row_major_sum.wrapping_add(matrix[row * n + col]);
+        }
+    }
+    std::hint::black_box(row_major_sum);
+    let row_major = start.elapsed();
+
+    let start = Instant::now();
+    let mut col_major_sum = 0u64;
+    for col in 0..n {
+        for row in 0..n {
+            col_major_sum = col_major_sum.wrapping_add(matrix[row * n + col]);
+        }
+    }
+    std::hint::black_box(col_major_sum);
+    let col_major = start.elapsed();
+
+    assert_eq!(row_major_sum, col_major_sum);
+    (row_major, col_major)
+}
+
+/// A Vec that fills its capacity has to reallocate: it finds a bigger block on the heap (roughly double the
+/// size), copies its elements over, and frees the old block. Recording (len, capacity, data pointer) after
+/// every push shows the capacity jumping in steps and the data pointer moving whenever the Vec couldn't
+/// grow in place.
+pub fn trace_vec_growth(pushes: usize) -> Vec<(usize, usize, *const i32)> {
+    let mut v: Vec<i32> = Vec::new();
+    let mut trace = Vec::new();
+
+    for i in 0..pushes {
+        v.push(i as i32);
+        trace.push((v.len(), v.capacity(), v.as_ptr()));
+    }
+
+    trace
+}
+
+pub fn vec_growth_demo() {
+    let trace = trace_vec_growth(100);
+    let mut prev: Option<(usize, *const i32)> = None;
+
+    println!("len | capacity | data pointer");
+    for &(len, capacity, ptr) in &trace {
+        assert!(len <= capacity);
+        // only show the pushes that changed the capacity
+        if prev.map(|(cap, _)| cap) != Some(capacity) {
+            // a reallocation only ever makes room for more
+            assert!(prev.is_none_or(|(cap, _)| cap < capacity));
+            let moved = match prev {
+                Some((_, prev_ptr)) if prev_ptr != ptr => " (moved)",
+                _ => "",
+            };
+            println!("{:3} | {:8} | {:p}{}", len, capacity, ptr, moved);
+        }
+        prev = Some((capacity, ptr));
+    }
+}
+
 /// VIRTUAL MEMORY
 /// PAGE - a fixed-size block of words of real memory, typically 4KB for 64-bits operating systems.
 /// WORD - ant type that is size of a pointer. This corresponds to the width of the CPU's registers.
@@ -138,7 +781,6 @@ pub fn stack_and_heap() {
 /// SEGMENTATION FAULT - an error raised by the CPU when an illegal memory address is requested.
 /// MMU - a component of the CPU that manages memory address translation from virtual to real addresses.
 /// Maintains a cache of recently translated addresses called the TLB (Translation Lookaside Buffer).
-
 pub fn naive_scan_of_program_memory() {
     // results in segmentation fault
     let mut n_nonzero = 0;
@@ -155,24 +797,1185 @@ pub fn naive_scan_of_program_memory() {
     println!("non-zero bytes in memory: {n_nonzero}");
 }
 
+/// The WORD size of the machine this is running on, as Rust sees it: usize is defined to be pointer-sized, so
+/// its size is the width of an address, and on mainstream hardware that is the width of a general purpose
+/// register too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WordInfo {
+    pub pointer_bits: usize,
+    pub usize_bytes: usize,
+    pub is_64bit: bool,
+}
+
+pub fn word_info() -> WordInfo {
+    let usize_bytes = size_of::<usize>();
+    WordInfo {
+        pointer_bits: size_of::<*const u8>() * 8,
+        usize_bytes,
+        is_64bit: usize_bytes == 8,
+    }
+}
+
+pub fn word_info_demo() {
+    let info = word_info();
+    println!("{:?}", info);
+    println!("registers on this host are {} bits wide", info.pointer_bits);
+
+    assert_eq!(info.usize_bytes * 8, info.pointer_bits);
+    assert!(info.pointer_bits == 32 || info.pointer_bits == 64);
+    assert_eq!(info.is_64bit, cfg!(target_pointer_width = "64"));
+}
+
+/// The page size is chosen by the OS (and constrained by the hardware), so "typically 4KB" can be checked by
+/// asking: sysconf(_SC_PAGESIZE) on unix, GetSystemInfo on windows. Both are declared here directly rather
+/// than pulled in from the libc/windows crates. Anything else falls back to 4096.
+const FALLBACK_PAGE_SIZE: usize = 4096;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub fn page_size() -> usize {
+    use std::ffi::{c_int, c_long};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const _SC_PAGESIZE: c_int = 30;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const _SC_PAGESIZE: c_int = 29;
+
+    extern "C" {
+        fn sysconf(name: c_int) -> c_long;
+    }
+
+    // SAFETY: sysconf only reads system configuration, and returns -1 for an unsupported name.
+    let size = unsafe { sysconf(_SC_PAGESIZE) };
+    if size > 0 {
+        size as usize
+    } else {
+        FALLBACK_PAGE_SIZE
+    }
+}
+
+#[cfg(windows)]
+pub fn page_size() -> usize {
+    use std::ffi::c_void;
+
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct SystemInfo {
+        processor_architecture: u16,
+        reserved: u16,
+        page_size: u32,
+        minimum_application_address: *mut c_void,
+        maximum_application_address: *mut c_void,
+        active_processor_mask: usize,
+        number_of_processors: u32,
+        processor_type: u32,
+        allocation_granularity: u32,
+        processor_level: u16,
+        processor_revision: u16,
+    }
+
+    extern "system" {
+        fn GetSystemInfo(info: *mut SystemInfo);
+    }
+
+    let mut info = std::mem::MaybeUninit::<SystemInfo>::uninit();
+    // SAFETY: GetSystemInfo always fills in the whole struct.
+    let info = unsafe {
+        GetSystemInfo(info.as_mut_ptr());
+        info.assume_init()
+    };
+
+    match info.page_size {
+        0 => FALLBACK_PAGE_SIZE,
+        size => size as usize,
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    windows
+)))]
+pub fn page_size() -> usize {
+    FALLBACK_PAGE_SIZE
+}
+
+/// A PAGE FAULT doesn't have to be fatal: the OS turns a fault on a page the process isn't allowed to touch
+/// into a SIGSEGV (SIGBUS on macOS), and if the handler for it fixes whatever was wrong, returning from the
+/// handler retries the faulting instruction. This is how the OS itself handles faults behind the scenes, e.g.
+/// for pages swapped out to disk; here the fix is to lift the protection that caused the fault.
+///
+/// guarded_page_demo maps a fresh page with mmap, takes all access away with mprotect(PROT_NONE), then writes
+/// to it. The handler makes the page readable and writable again, and the write goes through on the retry.
+/// mprotect isn't on POSIX's list of async-signal-safe functions, but Linux and macOS both allow it here.
+/// The handlers are installed with sigaction, which hands back the whole previous action (its mask and flags
+/// as well as the handler) so that it can be put back exactly as it was afterwards. Like page_size, the
+/// functions and struct sigaction are declared directly rather than pulled in from the libc crate. The layout
+/// of struct sigaction is glibc's and musl's on Linux, which MIPS and Android's bionic don't share.
+#[cfg(all(
+    feature = "unsafe_demos",
+    any(
+        all(
+            target_os = "linux",
+            not(any(target_arch = "mips", target_arch = "mips64"))
+        ),
+        target_os = "macos",
+        target_os = "ios"
+    )
+))]
+pub fn guarded_page_demo() {
+    use std::ffi::{c_int, c_long, c_ulong, c_void};
+    use std::mem::MaybeUninit;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const PROT_NONE: c_int = 0;
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const MAP_PRIVATE: c_int = 0x02;
+    #[cfg(target_os = "linux")]
+    const MAP_ANONYMOUS: c_int = 0x20;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const MAP_ANONYMOUS: c_int = 0x1000;
+    const SIGSEGV: c_int = 11;
+    #[cfg(target_os = "linux")]
+    const SIGBUS: c_int = 7;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const SIGBUS: c_int = 10;
+
+    // sa_handler shares its slot with the three-argument sa_sigaction, which is only used with SA_SIGINFO
+    #[cfg(target_os = "linux")]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct SigAction {
+        sa_handler: usize,
+        // sigset_t has room for 1024 signals
+        sa_mask: [c_ulong; 1024 / c_ulong::BITS as usize],
+        sa_flags: c_int,
+        sa_restorer: usize,
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct SigAction {
+        sa_handler: usize,
+        sa_mask: u32,
+        sa_flags: c_int,
+    }
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        fn sigaction(signum: c_int, act: *const SigAction, old_act: *mut SigAction) -> c_int;
+    }
+
+    // the handler only gets the signal number, so the page it has to fix is left here for it
+    static GUARDED_PAGE: AtomicUsize = AtomicUsize::new(0);
+    static GUARDED_LEN: AtomicUsize = AtomicUsize::new(0);
+    static FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn on_fault(_signum: c_int) {
+        FAULTS.fetch_add(1, Ordering::SeqCst);
+        let page = GUARDED_PAGE.load(Ordering::SeqCst) as *mut c_void;
+        // SAFETY: the page was mapped by guarded_page_demo and stays mapped until the demo is done with it.
+        unsafe {
+            mprotect(
+                page,
+                GUARDED_LEN.load(Ordering::SeqCst),
+                PROT_READ | PROT_WRITE,
+            )
+        };
+    }
+
+    let len = page_size();
+    // SAFETY: an anonymous private mapping only creates new memory; MAP_FAILED (all ones) is checked for below.
+    let page = unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(page as usize, usize::MAX, "mmap failed");
+    GUARDED_PAGE.store(page as usize, Ordering::SeqCst);
+    GUARDED_LEN.store(len, Ordering::SeqCst);
+    FAULTS.store(0, Ordering::SeqCst);
+
+    // SAFETY: SigAction is all integers, and all zeros is an empty mask and no flags. The page belongs to this
+    // function. The write happens with the handler installed, which makes the page writable again before the
+    // write is retried; volatile stops the compiler from moving the access. sigaction fills in the previous
+    // actions whenever it succeeds, which is checked before they are read.
+    unsafe {
+        let mut action: SigAction = std::mem::zeroed();
+        action.sa_handler = on_fault as extern "C" fn(c_int) as usize;
+        let mut old_segv = MaybeUninit::<SigAction>::uninit();
+        let mut old_bus = MaybeUninit::<SigAction>::uninit();
+        assert_eq!(
+            sigaction(SIGSEGV, &action, old_segv.as_mut_ptr()),
+            0,
+            "sigaction failed"
+        );
+        assert_eq!(
+            sigaction(SIGBUS, &action, old_bus.as_mut_ptr()),
+            0,
+            "sigaction failed"
+        );
+
+        assert_eq!(mprotect(page, len, PROT_NONE), 0, "mprotect failed");
+        let byte = page as *mut u8;
+        std::ptr::write_volatile(byte, 42);
+        let value = std::ptr::read_volatile(byte);
+
+        sigaction(SIGSEGV, old_segv.as_ptr(), std::ptr::null_mut());
+        sigaction(SIGBUS, old_bus.as_ptr(), std::ptr::null_mut());
+        munmap(page, len);
+
+        // the standard library's own SIGSEGV handler, which detects stack overflows, is back with the
+        // SA_SIGINFO and SA_ONSTACK flags it needs, which signal() would have dropped
+        let mut restored = MaybeUninit::<SigAction>::uninit();
+        sigaction(SIGSEGV, std::ptr::null(), restored.as_mut_ptr());
+        let (restored, old_segv) = (restored.assume_init(), old_segv.assume_init());
+        assert_eq!(
+            (restored.sa_handler, restored.sa_flags),
+            (old_segv.sa_handler, old_segv.sa_flags)
+        );
+
+        assert_eq!(value, 42);
+    }
+
+    assert_eq!(FAULTS.load(Ordering::SeqCst), 1);
+    println!("wrote to a PROT_NONE page once the handler had fixed the fault");
+}
+
+/// A PAGE TABLE can be simulated with a map from VIRTUAL PAGE NUMBERS (vpn) to PHYSICAL FRAME NUMBERS (pfn).
+/// Because pages are a power of two in size, a virtual address splits cleanly into two parts: the high
+/// bits are the page number and the low bits are the offset within the page. Translation swaps the page
+/// number for the frame number it is mapped to and keeps the offset as it is. Asking for an address in a
+/// page that has no mapping is a PAGE FAULT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    pub vpn: usize,
+}
+
+pub struct PageTable {
+    page_size: usize,
+    entries: HashMap<usize, usize>,
+}
+
+impl PageTable {
+    pub fn new(page_size: usize) -> PageTable {
+        assert!(
+            page_size.is_power_of_two(),
+            "page size must be a power of two"
+        );
+
+        PageTable {
+            page_size,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn map(&mut self, vpn: usize, pfn: usize) {
+        self.entries.insert(vpn, pfn);
+    }
+
+    pub fn unmap(&mut self, vpn: usize) -> Option<usize> {
+        self.entries.remove(&vpn)
+    }
+
+    // split a virtual address into its page number and offset within that page
+    pub fn split(&self, vaddr: usize) -> (usize, usize) {
+        (vaddr / self.page_size, vaddr % self.page_size)
+    }
+
+    pub fn frame_of(&self, vpn: usize) -> Result<usize, PageFault> {
+        self.entries.get(&vpn).copied().ok_or(PageFault { vpn })
+    }
+
+    pub fn translate(&self, vaddr: usize) -> Result<usize, PageFault> {
+        let (vpn, offset) = self.split(vaddr);
+        let pfn = self.frame_of(vpn)?;
+
+        Ok(pfn * self.page_size + offset)
+    }
+}
+
+/// The MMU doesn't walk the page table on every memory access. It first checks the TLB, a small cache of
+/// recent vpn -> pfn translations, and only goes to the page table on a miss. This TLB holds a fixed number
+/// of entries and evicts the least recently used one when it is full. Entries are kept in order of use,
+/// least recent first. When a page is unmapped its entry has to be invalidated too, otherwise the TLB would
+/// keep translating to a frame that no longer belongs to the page.
+pub struct Tlb {
+    capacity: usize,
+    entries: VecDeque<(usize, usize)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Tlb {
+    pub fn new(capacity: usize) -> Tlb {
+        assert!(capacity > 0, "TLB needs room for at least one entry");
+
+        Tlb {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn translate(&mut self, table: &PageTable, vaddr: usize) -> Result<usize, PageFault> {
+        let (vpn, offset) = table.split(vaddr);
+
+        let pfn = match self.entries.iter().position(|&(cached, _)| cached == vpn) {
+            Some(i) => {
+                self.hits += 1;
+                let entry = self.entries.remove(i).unwrap();
+                self.entries.push_back(entry);
+                entry.1
+            }
+            None => {
+                self.misses += 1;
+                let pfn = table.frame_of(vpn)?;
+                if self.entries.len() == self.capacity {
+                    self.entries.pop_front();
+                }
+                self.entries.push_back((vpn, pfn));
+                pfn
+            }
+        };
+
+        Ok(pfn * table.page_size() + offset)
+    }
+
+    pub fn invalidate(&mut self, vpn: usize) {
+        self.entries.retain(|&(cached, _)| cached != vpn);
+    }
+
+    pub fn contains(&self, vpn: usize) -> bool {
+        self.entries.iter().any(|&(cached, _)| cached == vpn)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+/// DEMAND PAGING
+/// Virtual memory can be larger than physical memory because pages only need to be in RAM while they are
+/// being used. The rest sit on disk in SWAP SPACE. When a page that isn't resident is accessed, the page
+/// table lookup fails with a page fault. The OS handles the fault by picking a frame, swapping the page in
+/// from disk, and mapping it. If every frame is already taken, a resident page gets evicted first, which
+/// means writing it back to disk and unmapping it. This manager evicts pages in FIFO order: whichever page has
+/// been resident the longest goes first.
+///
+/// Addresses outside the virtual address space can't be paged in at all. Accessing one is a segmentation
+/// fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentationFault {
+    pub vaddr: usize,
+}
+
+pub struct MemoryManager {
+    table: PageTable,
+    physical: Vec<u8>,
+    disk: Vec<u8>,
+    n_frames: usize,
+    resident: VecDeque<usize>,
+    page_faults: u64,
+}
+
+impl MemoryManager {
+    pub fn new(page_size: usize, n_frames: usize, n_virtual_pages: usize) -> MemoryManager {
+        assert!(n_frames > 0, "need at least one physical frame");
+
+        MemoryManager {
+            table: PageTable::new(page_size),
+            physical: vec![0; n_frames * page_size],
+            disk: vec![0; n_virtual_pages * page_size],
+            n_frames,
+            resident: VecDeque::with_capacity(n_frames),
+            page_faults: 0,
+        }
+    }
+
+    // make sure the page holding `vaddr` is resident and return the physical address it maps to
+    pub fn access(&mut self, vaddr: usize) -> Result<usize, SegmentationFault> {
+        if vaddr >= self.disk.len() {
+            return Err(SegmentationFault { vaddr });
+        }
+
+        match self.table.translate(vaddr) {
+            Ok(paddr) => Ok(paddr),
+            Err(PageFault { vpn }) => {
+                self.page_faults += 1;
+                let pfn = self.free_frame();
+                self.swap_in(vpn, pfn);
+                Ok(self.table.translate(vaddr).unwrap())
+            }
+        }
+    }
+
+    pub fn read(&mut self, vaddr: usize) -> Result<u8, SegmentationFault> {
+        let paddr = self.access(vaddr)?;
+        Ok(self.physical[paddr])
+    }
+
+    pub fn write(&mut self, vaddr: usize, value: u8) -> Result<(), SegmentationFault> {
+        let paddr = self.access(vaddr)?;
+        self.physical[paddr] = value;
+        Ok(())
+    }
+
+    pub fn page_faults(&self) -> u64 {
+        self.page_faults
+    }
+
+    pub fn is_resident(&self, vpn: usize) -> bool {
+        self.resident.contains(&vpn)
+    }
+
+    // use the next unused frame, or evict the oldest resident page to make one
+    fn free_frame(&mut self) -> usize {
+        if self.resident.len() < self.n_frames {
+            return self.resident.len();
+        }
+
+        let victim = self.resident.pop_front().unwrap();
+        let pfn = self.table.unmap(victim).unwrap();
+        self.swap_out(victim, pfn);
+        pfn
+    }
+
+    fn swap_in(&mut self, vpn: usize, pfn: usize) {
+        let page_size = self.table.page_size();
+        let frame = pfn * page_size..(pfn + 1) * page_size;
+        let page = vpn * page_size..(vpn + 1) * page_size;
+
+        self.physical[frame].copy_from_slice(&self.disk[page]);
+        self.table.map(vpn, pfn);
+        self.resident.push_back(vpn);
+    }
+
+    fn swap_out(&mut self, vpn: usize, pfn: usize) {
+        let page_size = self.table.page_size();
+        let frame = pfn * page_size..(pfn + 1) * page_size;
+        let page = vpn * page_size..(vpn + 1) * page_size;
+
+        self.disk[page].copy_from_slice(&self.physical[frame]);
+    }
+}
+
 static GLOBAL: i32 = 1000;
 
-fn noop() -> *const i32 {
+// The address a local had while noop was running. It's returned as a plain number rather than a pointer,
+// since the local is gone by the time the caller gets it and a pointer to it would dangle.
+fn noop() -> usize {
     let noop_local = 12345;
-    &noop_local as *const i32
+    std::hint::black_box(&noop_local) as *const i32 as usize
+}
+
+/// Box::into_raw hands ownership of the heap allocation over to a raw pointer: the Box is consumed
+/// without being dropped, so nothing will ever free the allocation unless it is handed back with
+/// Box::from_raw. The safety invariant is that every into_raw is paired with exactly one from_raw on the
+/// same pointer. Skipping the from_raw leaks the allocation, and calling it twice frees it twice.
+///
+/// This boxes `value`, prints the heap address it ended up at, then reclaims the allocation and returns
+/// the value, so nothing is leaked.
+pub fn box_roundtrip<T>(value: T) -> T {
+    let ptr: *mut T = Box::into_raw(Box::new(value));
+    println!("{:p}", ptr);
+
+    // SAFETY: `ptr` came from Box::into_raw above and this is the only from_raw for it.
+    let boxed = unsafe { Box::from_raw(ptr) };
+    *boxed
 }
 
 pub fn printing_virtual_addresses() {
     let local_str = "a";
     let local_int = 123;
-    let boxed_str = Box::new("b");
-    let boxed_int = Box::new(456);
     let fn_int = noop();
 
     println!("GLOBAL:    {:p}", &GLOBAL as *const i32);
     println!("local_str: {:p}", local_str as *const str);
     println!("local_int: {:p}", &local_int as *const i32);
-    println!("boxed_str: {:p}", Box::into_raw(boxed_str));
-    println!("boxed_int: {:p}", Box::into_raw(boxed_int));
-    println!("fn_int:    {:p}", fn_int);
+    print!("boxed_str: ");
+    box_roundtrip("b");
+    print!("boxed_int: ");
+    box_roundtrip(456);
+    println!("fn_int:    {:#x}", fn_int);
+}
+
+/// SIZE AND ALIGNMENT
+/// Every type has a SIZE, the number of bytes a value of that type occupies, and an ALIGNMENT, a power
+/// of two that the address of every value of that type must be a multiple of. The size is always a
+/// multiple of the alignment, so that values can be placed back to back in an array.
+pub fn variant_report<T>() {
+    println!("{}:", std::any::type_name::<T>());
+    println!("  size:  {:?} bytes", size_of::<T>());
+    println!("  align: {:?} bytes", align_of::<T>());
+}
+
+/// ENUM LAYOUT
+/// An enum is stored as space for its largest variant plus a DISCRIMINANT: a hidden integer recording
+/// which variant is currently live. The discriminant usually costs more than the handful of bits it needs,
+/// since it gets padded out to the alignment of the payload.
+///
+/// NICHE OPTIMISATION - when a payload type has bit patterns that can never be valid (a reference can
+/// never be null, a bool can only ever be 0 or 1), the compiler can use one of those invalid patterns to
+/// encode another variant, so no separate discriminant is stored. This is why Option<&T> is the same size as
+/// &T: None is just the null pointer. A u8, on the other hand, uses all 256 of its bit patterns, so Option<u8>
+/// needs an extra byte for the discriminant.
+#[allow(dead_code)]
+enum Shape {
+    Circle(f32),
+    Square(f32),
+    Rectangle(f32, f32),
+}
+
+#[allow(dead_code)]
+enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+pub fn enum_layout_demo() {
+    assert_eq!(size_of::<Option<&u8>>(), size_of::<&u8>());
+    assert_eq!(size_of::<Option<Box<i32>>>(), size_of::<Box<i32>>());
+    assert!(size_of::<Option<u8>>() > size_of::<u8>());
+
+    variant_report::<&u8>();
+    variant_report::<Option<&u8>>();
+    variant_report::<u8>();
+    variant_report::<Option<u8>>();
+    variant_report::<Shape>();
+    variant_report::<Direction>();
+    println!();
+
+    // the largest variant of Shape is Rectangle, holding two f32s
+    let shape_payload = 2 * size_of::<f32>();
+    println!(
+        "Shape:     {} byte payload + {} byte discriminant",
+        shape_payload,
+        size_of::<Shape>() - shape_payload
+    );
+
+    // Direction has no payload at all, so it is nothing but a discriminant
+    println!(
+        "Direction: 0 byte payload + {} byte discriminant",
+        size_of::<Direction>()
+    );
+    println!(
+        "Option<u8>: 1 byte payload + {} byte discriminant",
+        size_of::<Option<u8>>() - size_of::<u8>()
+    );
+    println!(
+        "Option<&u8>: {} byte payload + {} byte discriminant",
+        size_of::<&u8>(),
+        size_of::<Option<&u8>>() - size_of::<&u8>()
+    );
+}
+
+/// FIELD OFFSETS AND PADDING
+/// A #[repr(C)] struct lays its fields out in declaration order, the way C does. Each field is placed at the
+/// next offset that is a multiple of its own alignment, leaving PADDING bytes in between where necessary,
+/// and the struct as a whole is padded at the end so its size is a multiple of its largest field alignment.
+///
+/// In Mixed below, `b` needs a 4-byte aligned offset, so 3 bytes of padding follow `a`. The struct's
+/// alignment is 4, so 2 more bytes of padding follow `c` to round the size up from 10 to 12.
+#[allow(dead_code)]
+#[repr(C)]
+pub struct Mixed {
+    a: u8,
+    b: u32,
+    c: u16,
+}
+
+pub fn field_offsets_demo() {
+    assert_eq!(offset_of!(Mixed, a), 0);
+    assert_eq!(offset_of!(Mixed, b), 4);
+    assert_eq!(offset_of!(Mixed, c), 8);
+    assert_eq!(size_of::<Mixed>(), 12);
+
+    println!("field  | offset | size");
+    println!("a: u8  | {:6} | {}", offset_of!(Mixed, a), size_of::<u8>());
+    println!("b: u32 | {:6} | {}", offset_of!(Mixed, b), size_of::<u32>());
+    println!("c: u16 | {:6} | {}", offset_of!(Mixed, c), size_of::<u16>());
+    println!();
+    variant_report::<Mixed>();
+}
+
+/// REPR
+/// The same three fields can take up different amounts of space depending on the struct's representation:
+/// - the default repr(Rust) makes no promises about field order, so the compiler is free to sort the fields to
+///   minimise padding (here putting `b` first and the two u8s after it).
+/// - repr(C) keeps declaration order, so a u8 before and a u8 after the u32 each end up padded out to 4 bytes.
+/// - repr(packed) removes all padding and drops the struct's alignment to 1, so fields may sit at any offset.
+///
+/// Packing is a hazard: `b` in Packed is generally not at an address that is a multiple of 4. Taking a
+/// reference to it would create an unaligned &u32, which is undefined behaviour, so the compiler refuses to do
+/// it. Fields of a packed struct have to be copied out by value (or read with ptr::read_unaligned) instead. On
+/// some architectures unaligned loads are also slower, or not supported by the hardware at all.
+#[allow(dead_code)]
+pub struct ReprRust {
+    a: u8,
+    b: u32,
+    c: u8,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+pub struct ReprC {
+    a: u8,
+    b: u32,
+    c: u8,
+}
+
+#[allow(dead_code)]
+#[repr(C, packed)]
+pub struct Packed {
+    a: u8,
+    b: u32,
+    c: u8,
+}
+
+pub fn repr_comparison() {
+    assert!(size_of::<Packed>() < size_of::<ReprC>());
+    assert!(size_of::<ReprRust>() <= size_of::<ReprC>());
+
+    variant_report::<ReprRust>();
+    variant_report::<ReprC>();
+    variant_report::<Packed>();
+
+    let packed = Packed { a: 1, b: 2, c: 3 };
+    // `&packed.b` would not compile; the braces copy the field out first
+    let b = { packed.b };
+    println!("Packed.b = {} at offset {}", b, offset_of!(Packed, b));
+}
+
+/// PADDING is whatever part of a struct's size isn't taken up by its fields. Rust has no way to list a struct's
+/// fields and their types at runtime, so the example structs report them through FieldSizes, and padding_bytes
+/// takes their total away from size_of. It can't tell the padding between fields from the padding at the end.
+pub trait FieldSizes {
+    const FIELD_SIZES: &'static [usize];
+}
+
+pub fn padding_bytes<T: FieldSizes>() -> usize {
+    size_of::<T>() - T::FIELD_SIZES.iter().sum::<usize>()
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+pub struct ByteThenWord {
+    a: u8,
+    b: u32,
+}
+
+impl FieldSizes for ByteThenWord {
+    const FIELD_SIZES: &'static [usize] = &[size_of::<u8>(), size_of::<u32>()];
+}
+
+impl FieldSizes for Mixed {
+    const FIELD_SIZES: &'static [usize] = &[size_of::<u8>(), size_of::<u32>(), size_of::<u16>()];
+}
+
+impl FieldSizes for ReprRust {
+    const FIELD_SIZES: &'static [usize] = &[size_of::<u8>(), size_of::<u32>(), size_of::<u8>()];
+}
+
+impl FieldSizes for ReprC {
+    const FIELD_SIZES: &'static [usize] = &[size_of::<u8>(), size_of::<u32>(), size_of::<u8>()];
+}
+
+impl FieldSizes for Packed {
+    const FIELD_SIZES: &'static [usize] = &[size_of::<u8>(), size_of::<u32>(), size_of::<u8>()];
+}
+
+pub fn padding_demo() {
+    // all 3 bytes of ByteThenWord's padding sit between `a` and `b`, since `b` starts at the next multiple of 4
+    assert_eq!(padding_bytes::<ByteThenWord>(), 3);
+    assert_eq!(offset_of!(ByteThenWord, b) - size_of::<u8>(), 3);
+    assert_eq!(padding_bytes::<Mixed>(), 5);
+    assert_eq!(padding_bytes::<ReprC>(), 6);
+    assert_eq!(padding_bytes::<Packed>(), 0);
+
+    println!(
+        "ByteThenWord: {} bytes of padding",
+        padding_bytes::<ByteThenWord>()
+    );
+    println!(
+        "Mixed:        {} bytes of padding",
+        padding_bytes::<Mixed>()
+    );
+    println!(
+        "ReprRust:     {} bytes of padding",
+        padding_bytes::<ReprRust>()
+    );
+    println!(
+        "ReprC:        {} bytes of padding",
+        padding_bytes::<ReprC>()
+    );
+    println!(
+        "Packed:       {} bytes of padding",
+        padding_bytes::<Packed>()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // bumps a shared counter when dropped, to check that containers drop what they hold
+    struct CountDrops(Rc<Cell<u32>>);
+
+    impl Drop for CountDrops {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn examples_run() {
+        enum_layout_demo();
+        printing_virtual_addresses();
+        where_does_it_live();
+        my_box_demo();
+        vec_growth_demo();
+        field_offsets_demo();
+        repr_comparison();
+        drop_order_demo();
+        rc_refcount_demo();
+        hexdump_demo();
+        word_info_demo();
+        padding_demo();
+        references_and_memory();
+        raw_pointers();
+        stack_and_heap();
+    }
+
+    #[test]
+    fn niches_make_some_options_free() {
+        assert_eq!(size_of::<Option<&u8>>(), size_of::<&u8>());
+        assert_eq!(size_of::<Option<Box<u64>>>(), size_of::<Box<u64>>());
+        assert_eq!(size_of::<Option<bool>>(), size_of::<bool>());
+        assert_eq!(size_of::<Option<u8>>(), 2);
+        assert_eq!(size_of::<Direction>(), 1);
+    }
+
+    #[test]
+    fn box_roundtrip_returns_the_value() {
+        assert_eq!(box_roundtrip(String::from("heap")), "heap");
+        assert_eq!(box_roundtrip(vec![1, 2, 3]), [1, 2, 3]);
+        assert_eq!(box_roundtrip(()), ());
+    }
+
+    #[test]
+    fn stack_grows_down_gives_the_same_answer_every_time() {
+        let first = stack_grows_down();
+        assert!((0..100).all(|_| stack_grows_down() == first));
+    }
+
+    // every mainstream target Rust supports grows its stack down
+    #[cfg(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))]
+    #[test]
+    fn stack_grows_down_on_common_targets() {
+        assert!(stack_grows_down());
+    }
+
+    #[test]
+    fn region_of_recognises_each_region() {
+        let local = 0u64;
+        let boxed = std::hint::black_box(Box::new(0u64));
+        assert_eq!(region_of(&local), MemoryRegion::Stack);
+        assert_eq!(region_of(&*boxed), MemoryRegion::Heap);
+        assert_eq!(region_of(&GLOBAL), MemoryRegion::Static);
+
+        // a big allocation gets its own mapping, and a zeroed static with interior mutability
+        // lives in .bss rather than next to the code
+        let big = std::hint::black_box(vec![0u8; 1 << 20]);
+        assert_eq!(region_of(&big[0]), MemoryRegion::Heap);
+        static ZEROED: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        assert_eq!(region_of(&ZEROED), MemoryRegion::Static);
+
+        // the Box's pointee isn't anywhere near the locals next to it
+        let local_addr = &local as *const u64 as usize;
+        let boxed_addr = &*boxed as *const u64 as usize;
+        assert!(local_addr.abs_diff(boxed_addr) > 4096);
+    }
+
+    #[test]
+    fn tagged_ptr_keeps_pointer_and_tag_apart() {
+        assert_eq!(TaggedPtr::<u64>::TAG_BITS, 3);
+        assert_eq!(TaggedPtr::<u8>::TAG_BITS, 0);
+
+        let mut value = 7u64;
+        let mut tagged = TaggedPtr::new(&mut value, 5);
+        assert_eq!(tagged.tag(), 5);
+        assert_eq!(tagged.ptr(), &mut value as *mut u64);
+
+        tagged.set_tag(2);
+        assert_eq!(tagged.tag(), 2);
+        // SAFETY: the untagged pointer is the address of `value`, which is still alive
+        assert_eq!(unsafe { *tagged.ptr() }, 7);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn tagged_ptr_rejects_oversized_tag() {
+        let mut value = 0u32;
+        TaggedPtr::new(&mut value, 4);
+    }
+
+    #[test]
+    fn my_box_derefs_and_drops_its_value() {
+        let mut boxed = MyBox::new(41);
+        *boxed += 1;
+        assert_eq!(*boxed, 42);
+
+        // the value inside is dropped exactly once, when the MyBox is
+        let drops = Rc::new(Cell::new(0));
+        let counted = MyBox::new(CountDrops(Rc::clone(&drops)));
+        assert_eq!(drops.get(), 0);
+        std::mem::drop(counted);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn my_box_of_zero_sized_type() {
+        let unit = MyBox::new(());
+        assert_eq!(*unit, ());
+    }
+
+    #[test]
+    fn page_table_translates_within_a_page() {
+        let mut table = PageTable::new(4096);
+        assert_eq!(table.page_size(), 4096);
+        assert_eq!(table.split(0x3_0ABC), (0x30, 0xABC));
+
+        table.map(0x30, 7);
+        assert_eq!(table.frame_of(0x30), Ok(7));
+        assert_eq!(table.translate(0x3_0ABC), Ok(0x7ABC));
+        assert_eq!(table.translate(0x3_0000), Ok(0x7000));
+        assert_eq!(table.translate(0x3_1000), Err(PageFault { vpn: 0x31 }));
+
+        assert_eq!(table.unmap(0x30), Some(7));
+        assert_eq!(table.unmap(0x30), None);
+        assert_eq!(table.translate(0x3_0ABC), Err(PageFault { vpn: 0x30 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn page_table_rejects_odd_page_size() {
+        PageTable::new(1000);
+    }
+
+    #[test]
+    fn tlb_misses_then_hits() {
+        let mut table = PageTable::new(16);
+        table.map(0, 4);
+        let mut tlb = Tlb::new(2);
+        assert_eq!(tlb.translate(&table, 0x3), Ok(0x43));
+        assert_eq!(tlb.translate(&table, 0x3), Ok(0x43));
+        assert_eq!((tlb.hits(), tlb.misses()), (1, 1));
+    }
+
+    #[test]
+    fn tlb_evicts_least_recently_used() {
+        let mut table = PageTable::new(16);
+        for vpn in 0..3 {
+            table.map(vpn, vpn + 10);
+        }
+        let mut tlb = Tlb::new(2);
+
+        assert_eq!(tlb.translate(&table, 0x05), Ok(0xA5));
+        assert_eq!(tlb.translate(&table, 0x15), Ok(0xB5));
+        // touch page 0 so page 1 is now the least recently used
+        assert_eq!(tlb.translate(&table, 0x06), Ok(0xA6));
+        assert_eq!(tlb.translate(&table, 0x25), Ok(0xC5));
+        assert!(tlb.contains(0) && tlb.contains(2) && !tlb.contains(1));
+        assert_eq!((tlb.hits(), tlb.misses()), (1, 3));
+
+        // a fault isn't cached
+        assert_eq!(tlb.translate(&table, 0x35), Err(PageFault { vpn: 3 }));
+        assert_eq!(tlb.misses(), 4);
+        assert!(tlb.contains(0) && tlb.contains(2));
+    }
+
+    #[test]
+    fn tlb_invalidate_stops_stale_translations() {
+        let mut table = PageTable::new(16);
+        table.map(0, 1);
+        let mut tlb = Tlb::new(4);
+        assert_eq!(tlb.translate(&table, 3), Ok(0x13));
+
+        table.unmap(0);
+        table.map(0, 2);
+        assert_eq!(tlb.translate(&table, 3), Ok(0x13)); // stale
+        tlb.invalidate(0);
+        assert!(!tlb.contains(0));
+        assert_eq!(tlb.translate(&table, 3), Ok(0x23));
+    }
+
+    #[test]
+    fn memory_manager_swaps_pages_in_fifo_order() {
+        let mut mm = MemoryManager::new(4, 2, 4);
+        assert_eq!(mm.write(0, 0xAA), Ok(()));
+        assert_eq!(mm.write(4, 0xBB), Ok(()));
+        assert_eq!(mm.page_faults(), 2);
+        assert!(mm.is_resident(0) && mm.is_resident(1));
+
+        // page 0 has been resident the longest, so it's the one evicted for page 2
+        assert_eq!(mm.read(8), Ok(0));
+        assert!(!mm.is_resident(0) && mm.is_resident(2));
+        assert_eq!(mm.page_faults(), 3);
+
+        // the write to page 0 survived the trip to disk and back
+        assert_eq!(mm.read(0), Ok(0xAA));
+        assert!(!mm.is_resident(1));
+        assert_eq!(mm.read(4), Ok(0xBB));
+        assert_eq!(mm.page_faults(), 5);
+
+        assert_eq!(mm.access(16), Err(SegmentationFault { vaddr: 16 }));
+    }
+
+    #[test]
+    fn cycling_through_more_pages_than_frames_faults_every_time() {
+        let mut mm = MemoryManager::new(4, 2, 3);
+        for _ in 0..3 {
+            for vpn in 0..3 {
+                mm.access(vpn * 4).unwrap();
+            }
+        }
+        assert_eq!(mm.page_faults(), 9);
+
+        // with a frame for every page, only the first touch of each faults
+        let mut mm = MemoryManager::new(4, 3, 3);
+        for _ in 0..3 {
+            for vpn in 0..3 {
+                mm.access(vpn * 4).unwrap();
+            }
+        }
+        assert_eq!(mm.page_faults(), 3);
+    }
+
+    #[test]
+    fn raw_list_is_lifo_and_frees_every_node() {
+        let mut list = RawList::new();
+        assert!(list.is_empty());
+        assert_eq!(list.pop_front(), None);
+
+        for i in 1..=3 {
+            list.push_front(i);
+        }
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!(list.pop_front(), Some(3));
+        assert_eq!(list.len(), 2);
+
+        let drops = Rc::new(Cell::new(0));
+        let mut counted = RawList::default();
+        for _ in 0..4 {
+            counted.push_front(CountDrops(Rc::clone(&drops)));
+        }
+        std::mem::drop(counted.pop_front());
+        assert_eq!(drops.get(), 1);
+        std::mem::drop(counted);
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn traversal_timing_returns_two_positive_durations() {
+        let (row_major, col_major) = traversal_timing(256);
+        assert!(row_major > Duration::ZERO);
+        assert!(col_major > Duration::ZERO);
+
+        // both traversals check they summed the same elements, whatever the size
+        traversal_timing(0);
+        traversal_timing(1);
+    }
+
+    #[test]
+    fn trace_vec_growth_records_every_push() {
+        assert!(trace_vec_growth(0).is_empty());
+        let trace = trace_vec_growth(1000);
+        assert_eq!(trace.len(), 1000);
+        assert!(trace
+            .iter()
+            .enumerate()
+            .all(|(i, &(len, _, _))| len == i + 1));
+
+        assert!(trace.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert!(trace.windows(2).any(|pair| pair[0].2 != pair[1].2));
+
+        assert!(trace.iter().all(|&(len, capacity, _)| len <= capacity));
+        for pair in trace.windows(2) {
+            let ((_, prev_capacity, prev_ptr), (_, capacity, ptr)) = (pair[0], pair[1]);
+            // the data only moves when the Vec reallocates, and that always grows it
+            if ptr != prev_ptr {
+                assert!(capacity > prev_capacity);
+            }
+        }
+        let reallocations = trace.windows(2).filter(|pair| pair[0].1 != pair[1].1);
+        assert!(reallocations.count() >= 2);
+    }
+
+    #[test]
+    fn repr_c_offsets_include_the_padding() {
+        assert_eq!(offset_of!(Mixed, a), 0);
+        // three bytes of padding after `a` put `b` on a 4-byte boundary
+        assert_eq!(offset_of!(Mixed, b), 4);
+        assert_eq!(offset_of!(Mixed, c), 8);
+        assert_eq!((size_of::<Mixed>(), align_of::<Mixed>()), (12, 4));
+    }
+
+    #[test]
+    fn packing_removes_the_padding() {
+        assert!(size_of::<Packed>() < size_of::<ReprC>());
+        assert_eq!((size_of::<Packed>(), align_of::<Packed>()), (6, 1));
+        assert_eq!(size_of::<ReprC>(), 12);
+        // free to reorder, Rust puts the two u8s side by side after the u32
+        assert_eq!(size_of::<ReprRust>(), 8);
+    }
+
+    #[test]
+    fn drop_order_is_reverse_declaration_after_explicit_drops() {
+        assert_eq!(
+            drop_order_demo(),
+            [
+                "boxed b",
+                "end of scope",
+                "pair first",
+                "pair second",
+                "boxed d",
+                "stack c",
+                "stack a",
+            ]
+        );
+    }
+
+    #[test]
+    fn rc_counts_go_up_and_back_down() {
+        assert_eq!(rc_refcount_demo(), vec![1, 2, 3, 2, 1]);
+    }
+
+    #[cfg(feature = "trace_alloc")]
+    #[test]
+    fn allocation_demo_runs() {
+        allocation_demo();
+        assert!(allocated_bytes() >= freed_bytes());
+    }
+
+    #[cfg(feature = "trace_alloc")]
+    #[test]
+    fn live_bytes_follow_a_vec_allocation() {
+        const SIZE: usize = 1 << 20;
+        // The other tests allocate and free on their own threads while this runs. Their
+        // frees can only pull live_bytes down and their allocations only push it up, so
+        // those are measured and allowed for on the side that would make the check fail.
+        let freed_before = freed_bytes();
+        let live_before = live_bytes();
+        let v = std::hint::black_box(Vec::<u8>::with_capacity(SIZE));
+        let live_during = live_bytes();
+        let freed_meanwhile = freed_bytes() - freed_before;
+        assert!(v.capacity() >= SIZE);
+        assert!(live_during + freed_meanwhile >= live_before + SIZE);
+
+        let allocated_before = allocated_bytes();
+        let live_during = live_bytes();
+        std::mem::drop(v);
+        let live_after = live_bytes();
+        let allocated_meanwhile = allocated_bytes() - allocated_before;
+        assert!(live_after + SIZE <= live_during + allocated_meanwhile);
+    }
+
+    #[test]
+    fn page_size_is_a_power_of_two() {
+        let size = page_size();
+        assert!(size.is_power_of_two());
+        assert!(size >= 4096);
+        assert_eq!(page_size(), size);
+    }
+
+    #[test]
+    fn hexdump_of_a_short_buffer() {
+        let data: Vec<u8> = (0..20).map(|i| i * 13).collect();
+        assert_eq!(
+            hexdump(&data, 0),
+            "00000000: 000d 1a27 3441 4e5b 6875 828f 9ca9 b6c3  ...'4AN[hu......\n\
+             00000010: d0dd eaf7                                ....\n"
+        );
+        assert_eq!(hexdump(&[], 0x200), "");
+    }
+
+    #[test]
+    fn word_info_matches_the_pointer_width() {
+        let info = word_info();
+        assert_eq!(info.usize_bytes * 8, info.pointer_bits);
+        assert!(info.pointer_bits == 32 || info.pointer_bits == 64);
+        assert_eq!(info.pointer_bits, usize::BITS as usize);
+    }
+
+    #[test]
+    fn byte_then_word_has_3_padding_bytes() {
+        assert_eq!(size_of::<ByteThenWord>(), 8);
+        assert_eq!(padding_bytes::<ByteThenWord>(), 3);
+        // the padding is between the fields rather than at the end
+        assert_eq!(offset_of!(ByteThenWord, b), 4);
+        // field reordering lets repr(Rust) pack as tightly as possible
+        assert_eq!(padding_bytes::<ReprRust>(), 2);
+    }
+
+    #[cfg(all(
+        feature = "unsafe_demos",
+        any(
+            all(
+                target_os = "linux",
+                not(any(target_arch = "mips", target_arch = "mips64"))
+            ),
+            target_os = "macos",
+            target_os = "ios"
+        )
+    ))]
+    #[test]
+    fn guarded_page_demo_recovers_from_the_fault() {
+        guarded_page_demo();
+        // and again, to check the first run put everything back
+        guarded_page_demo();
+    }
 }
@@ -0,0 +1,291 @@
+// Small containers shared between modules. Nothing here needs more than core and a
+// Vec, so it would carry over to a no_std build with alloc unchanged.
+
+// A FIFO holding at most `capacity` items. Pushing onto a full buffer drops the oldest
+// item to make room, so it always holds the most recent ones.
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T> {
+    slots: Vec<Option<T>>,
+    // index of the oldest item in slots
+    head: usize,
+    len: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub fn new(capacity: usize) -> RingBuffer<T> {
+        RingBuffer {
+            slots: (0..capacity).map(|_| None).collect(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // add an item as the newest, dropping the oldest if the buffer is full. With a
+    // capacity of 0 the item is dropped straight away.
+    pub fn push(&mut self, item: T) {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return;
+        }
+
+        if self.len == capacity {
+            self.slots[self.head] = Some(item);
+            self.head = (self.head + 1) % capacity;
+        } else {
+            self.slots[(self.head + self.len) % capacity] = Some(item);
+            self.len += 1;
+        }
+    }
+
+    // remove and return the newest item
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        let newest = (self.head + self.len) % self.capacity();
+        self.slots[newest].take()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.head = 0;
+        self.len = 0;
+    }
+
+    // change how many items the buffer holds, dropping the oldest ones if it shrinks
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let mut resized = RingBuffer::new(capacity);
+        for slot in 0..self.len {
+            let slot = (self.head + slot) % self.capacity();
+            if let Some(item) = self.slots[slot].take() {
+                resized.push(item);
+            }
+        }
+        *self = resized;
+    }
+
+    // the items from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len).filter_map(move |i| self.slots[(self.head + i) % self.capacity()].as_ref())
+    }
+}
+
+// Sixteen flags packed into the bits of a u16, bit i holding flag i, in place of a
+// [bool; 16] sixteen times the size. Counting and finding the set flags come down to
+// single instructions on the integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BitSet16(pub u16);
+
+impl BitSet16 {
+    pub fn new() -> BitSet16 {
+        BitSet16(0)
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.0 & BitSet16::bit(i) != 0
+    }
+
+    pub fn set(&mut self, i: usize) {
+        self.0 |= BitSet16::bit(i);
+    }
+
+    pub fn clear(&mut self, i: usize) {
+        self.0 &= !BitSet16::bit(i);
+    }
+
+    pub fn assign(&mut self, i: usize, value: bool) {
+        if value {
+            self.set(i);
+        } else {
+            self.clear(i);
+        }
+    }
+
+    // the number of flags set, its POPCOUNT
+    pub fn count(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    // the indices of the set flags, lowest first. Each step clears the lowest set bit,
+    // so this only loops as many times as there are flags set.
+    pub fn iter_set(&self) -> impl Iterator<Item = usize> {
+        let mut bits = self.0;
+        core::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let i = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            Some(i)
+        })
+    }
+
+    pub fn to_array(&self) -> [bool; 16] {
+        core::array::from_fn(|i| self.get(i))
+    }
+
+    fn bit(i: usize) -> u16 {
+        assert!(i < 16, "bit {} is out of range for a BitSet16", i);
+        1 << i
+    }
+}
+
+impl From<[bool; 16]> for BitSet16 {
+    fn from(flags: [bool; 16]) -> BitSet16 {
+        let mut set = BitSet16::new();
+        for (i, &flag) in flags.iter().enumerate() {
+            set.assign(i, flag);
+        }
+        set
+    }
+}
+
+pub fn bit_set_example() {
+    let mut set = BitSet16::new();
+    for i in [0, 3, 15, 3] {
+        set.set(i);
+    }
+    set.clear(0);
+    set.clear(7);
+
+    assert!(set.get(3) && set.get(15) && !set.get(0));
+    assert_eq!(set.iter_set().collect::<Vec<_>>(), vec![3, 15]);
+    assert_eq!(set.count(), 2);
+    assert_eq!(set.0, 0b1000_0000_0000_1000);
+
+    // count is the popcount, so it always agrees with iterating
+    for bits in [0, 1, 0xFFFF, 0xA5A5, 0x8001] {
+        let set = BitSet16(bits);
+        assert_eq!(set.count() as usize, set.iter_set().count());
+        assert_eq!(BitSet16::from(set.to_array()), set);
+    }
+    println!(
+        "{:016b} has bits {:?} set",
+        set.0,
+        set.iter_set().collect::<Vec<_>>()
+    );
+}
+
+pub fn ring_buffer_example() {
+    let mut buffer = RingBuffer::new(3);
+    for i in 1..=5 {
+        buffer.push(i);
+    }
+
+    // 1 and 2 were pushed out by 4 and 5
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![3, 4, 5]);
+
+    assert_eq!(buffer.pop_back(), Some(5));
+    buffer.push(6);
+    buffer.push(7);
+    assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![4, 6, 7]);
+
+    buffer.set_capacity(2);
+    assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![6, 7]);
+
+    let mut empty = RingBuffer::new(0);
+    empty.push(1);
+    assert_eq!(empty.pop_back(), None);
+    println!("ring buffer holds {:?}", buffer.iter().collect::<Vec<_>>());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_run() {
+        ring_buffer_example();
+        bit_set_example();
+    }
+
+    #[test]
+    fn ring_buffer_wraps_around() {
+        let mut buffer = RingBuffer::new(4);
+        for i in 0..10 {
+            buffer.push(i);
+        }
+
+        // 0 to 5 were dropped, and iteration runs oldest to newest
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+        assert_eq!(buffer.pop_back(), Some(9));
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![6, 7, 8]);
+    }
+
+    #[test]
+    fn ring_buffer_clear_and_grow() {
+        let mut buffer = RingBuffer::new(2);
+        buffer.push('a');
+        buffer.push('b');
+        buffer.push('c');
+        buffer.set_capacity(4);
+        assert_eq!(buffer.capacity(), 4);
+        buffer.push('d');
+        assert_eq!(buffer.iter().copied().collect::<String>(), "bcd");
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop_back(), None);
+        assert_eq!(buffer.capacity(), 4);
+    }
+
+    #[test]
+    fn bit_set_set_clear_and_iterate() {
+        let mut set = BitSet16::new();
+        set.set(1);
+        set.set(4);
+        set.set(9);
+        set.clear(4);
+        assert!(set.get(1) && set.get(9) && !set.get(4));
+        assert_eq!(set.iter_set().collect::<Vec<_>>(), vec![1, 9]);
+        assert_eq!(set.count(), 2);
+
+        for bits in [0u16, 0x00FF, 0x1234, u16::MAX] {
+            let set = BitSet16(bits);
+            assert_eq!(
+                set.count(),
+                set.to_array().iter().filter(|&&b| b).count() as u32
+            );
+        }
+    }
+
+    #[test]
+    fn bit_set_assign_and_array() {
+        let mut set = BitSet16::new();
+        assert!(set.is_empty());
+        set.assign(5, true);
+        set.assign(9, true);
+        set.assign(5, false);
+        assert_eq!(set, BitSet16(1 << 9));
+
+        let mut flags = [false; 16];
+        flags[9] = true;
+        assert_eq!(set.to_array(), flags);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn bit_set_rejects_index_16() {
+        BitSet16::new().set(16);
+    }
+}
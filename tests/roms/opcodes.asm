@@ -0,0 +1,178 @@
+; Opcode test ROM. Each test leaves its result in V0 and the value it should be in V1,
+; then calls check, which draws the test's number if they match and leaves a gap if
+; they don't. Tests that also check VF overwrite V0 with 0xEE if the flag is wrong.
+; A full pass shows the hex digits 0 to B on the first row and C to F on the second.
+
+        LD V8, 0            ; test number
+        LD VA, 0            ; where to draw it
+        LD VB, 0
+
+; 0: 6XNN
+        LD V0, 0x42
+        LD V1, 0x42
+        CALL check
+
+; 1: 8XY0
+        LD V2, 0x17
+        LD V0, V2
+        LD V1, 0x17
+        CALL check
+
+; 2: 8XY1
+        LD V0, 0x0F
+        LD V2, 0xF0
+        OR V0, V2
+        LD V1, 0xFF
+        CALL check
+
+; 3: 8XY2
+        LD V0, 0x3C
+        LD V2, 0x0F
+        AND V0, V2
+        LD V1, 0x0C
+        CALL check
+
+; 4: 8XY3
+        LD V0, 0x3C
+        LD V2, 0x0F
+        XOR V0, V2
+        LD V1, 0x33
+        CALL check
+
+; 5: 8XY4 with a carry
+        LD V0, 0xFF
+        LD V2, 2
+        ADD V0, V2
+        SE VF, 1
+        LD V0, 0xEE
+        LD V1, 1
+        CALL check
+
+; 6: 8XY5 with a borrow
+        LD V0, 3
+        LD V2, 5
+        SUB V0, V2
+        SE VF, 0
+        LD V0, 0xEE
+        LD V1, 254
+        CALL check
+
+; 7: 8XY7 without one
+        LD V0, 3
+        LD V2, 5
+        SUBN V0, V2
+        SE VF, 1
+        LD V0, 0xEE
+        LD V1, 2
+        CALL check
+
+; 8: 8XY6 shifting a 1 out
+        LD V0, 5
+        SHR V0
+        SE VF, 1
+        LD V0, 0xEE
+        LD V1, 2
+        CALL check
+
+; 9: 8XYE shifting a 1 out
+        LD V0, 0x81
+        SHL V0
+        SE VF, 1
+        LD V0, 0xEE
+        LD V1, 2
+        CALL check
+
+; A: FX33, read back with FX65
+        LD V0, 159
+        LD I, scratch
+        LD B, V0
+        LD V2, [I]
+        SE V1, 5
+        LD V0, 0xEE
+        SE V2, 9
+        LD V0, 0xEE
+        LD V1, 1
+        CALL check
+
+; B: FX55 and FX65 round trip
+        LD V0, 0xA1
+        LD V1, 0xB2
+        LD I, scratch
+        LD [I], V1
+        LD V0, 0
+        LD V1, 0
+        LD V1, [I]
+        SE V1, 0xB2
+        LD V0, 0xEE
+        LD V1, 0xA1
+        CALL check
+
+; C: BNNN
+        LD V0, 4
+        JP V0, table
+table:  LD V0, 0xEE
+        JP table_done
+        LD V0, 0x77
+table_done:
+        LD V1, 0x77
+        CALL check
+
+; D: 3XNN, 4XNN, 5XY0 and 9XY0, each both skipping and not. Every add of 0x10 should
+; be skipped and every add of 1 should run.
+        LD V0, 0
+        SNE V0, 0
+        ADD V0, 1
+        SNE V0, 7
+        ADD V0, 0x10
+        SE V0, 1
+        ADD V0, 0x10
+        SE V0, 7
+        ADD V0, 1
+        LD V2, 2
+        SE V0, V2
+        ADD V0, 0x10
+        SNE V0, V2
+        ADD V0, 1
+        LD V2, 9
+        SNE V0, V2
+        ADD V0, 0x10
+        LD V1, 3
+        CALL check
+
+; E: 7XNN wrapping without touching VF
+        LD VF, 5
+        LD V0, 0xFF
+        ADD V0, 2
+        SE VF, 5
+        LD V0, 0xEE
+        LD V1, 1
+        CALL check
+
+; F: FX1E
+        LD I, scratch
+        LD V0, 1
+        ADD I, V0
+        LD V0, 0x5A
+        LD [I], V0
+        LD I, scratch
+        LD V1, [I]
+        LD V0, V1
+        LD V1, 0x5A
+        CALL check
+
+end:    JP end
+
+check:  SE V0, V1
+        JP next
+        LD F, V8
+        DRW VA, VB, 5
+next:   ADD VA, 5
+        ADD V8, 1
+        SE VA, 60
+        RET
+        LD VA, 0
+        ADD VB, 6
+        RET
+
+scratch:
+        DB 0, 0, 0